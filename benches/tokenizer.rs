@@ -0,0 +1,62 @@
+//! Measures how the time taken to tokenize a ledger grows with the size of the ledger. Run with
+//! `cargo bench --bench tokenizer`. Tokenizing is linear, so the time per megabyte should stay
+//! about the same as the ledger doubles in size.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use recount::tokenizer::Tokenizer;
+
+/// The number of times each ledger is tokenized, the fastest time is reported.
+const RUNS: usize = 5;
+
+/// Returns a ledger containing `transactions` transactions between two accounts.
+fn ledger(transactions: usize) -> String {
+    let mut ledger = String::from(
+        "option \"operating_currency\" \"GBP\"\n\
+         2020-01-01 open Assets:Bank GBP\n\
+         2020-01-01 open Expenses:Groceries GBP\n\n",
+    );
+    for i in 0..transactions {
+        let (month, day) = (i / 28 % 12 + 1, i % 28 + 1);
+        ledger.push_str(&format!(
+            "2020-{month:02}-{day:02} * \"Shop {i}\" \"Groceries\" ; weekly shop\n\
+             \x20 Expenses:Groceries  {}.{:02} GBP\n\
+             \x20 Assets:Bank\n\n",
+            i % 100,
+            i % 97
+        ));
+    }
+    ledger
+}
+
+/// Returns the fastest time taken to tokenize `ledger`.
+fn time(ledger: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(Tokenizer::new(ledger).count());
+            start.elapsed()
+        })
+        .min()
+        .expect("there's at least one run")
+}
+
+fn main() {
+    println!(
+        "{:>12} {:>10} {:>12} {:>12}",
+        "transactions", "size (MB)", "time", "time per MB"
+    );
+    for transactions in [12_500, 25_000, 50_000, 100_000, 200_000] {
+        let ledger = ledger(transactions);
+        let megabytes = ledger.len() as f64 / 1_000_000.0;
+        let time = time(&ledger);
+        println!(
+            "{transactions:>12} {megabytes:>10.2} {:>12.2?} {:>12.2?}",
+            time,
+            time.div_f64(megabytes)
+        );
+    }
+}
@@ -1,23 +1,55 @@
+use std::collections::HashMap;
+
 use date::Date;
 use rust_decimal::Decimal;
-use rust_decimal::dec;
 use thiserror::Error;
 
-use crate::types::{AccountId, Amount};
+use crate::{
+    inventory::{BookingError, ConversionError, CostSpec, Inventory, Position},
+    options::{LedgerOptions, OptionError, ToleranceDefaults},
+    prices::PriceDb,
+    types::{AccountId, AccountType, Amount, BookingMethod},
+};
 
-// Transactions can be out of balance by a maximum of this amount (this is inline with Beancount)
-const TOLERANCE: Decimal = dec!(0.005);
+/// Returns the tolerance inferred from the precision of `number`, which is half of one unit of its
+/// last decimal place e.g. 0.005 for `1234.56`. Returns [`None`] if `number` is an integer.
+fn inferred_tolerance(number: Decimal) -> Option<Decimal> {
+    (number.scale() > 0).then(|| (Decimal::new(1, number.scale()) / Decimal::TWO).normalize())
+}
+
+/// Returns the tolerance for a balance assertion of `amount`. This is one unit of the last decimal
+/// place of `amount`, or the default tolerance for the currency if `amount` is an integer.
+fn balance_tolerance(amount: &Amount, defaults: &ToleranceDefaults) -> Decimal {
+    if amount.amount.scale() == 0 {
+        defaults.get(&amount.currency)
+    } else {
+        Decimal::new(1, amount.amount.scale())
+    }
+}
 
 /// Represents an account in the [`AccountsDocument`].
 #[derive(Debug)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct Account {
     pub(crate) id: AccountId,
-    pub(crate) currency: String,
+    /// The commodities the account can hold, an empty list allows any commodity.
+    pub(crate) currencies: Vec<String>,
     pub(crate) opening_date: Date,
+    /// The date of the close directive, postings dated after this are rejected.
+    pub(crate) closing_date: Option<Date>,
+    /// How reductions of lots held in the account are booked.
+    pub(crate) booking_method: BookingMethod,
 }
 
-/// An iterator over accounts and balances returned by [`AccountsDocument::balances`].
+impl Account {
+    /// Returns `true` if the account can hold `currency`.
+    fn allows(&self, currency: &str) -> bool {
+        self.currencies.is_empty() || self.currencies.iter().any(|c| c == currency)
+    }
+}
+
+/// An iterator over accounts and balances returned by [`AccountsDocument::balances`]. Closed
+/// accounts are skipped.
 #[cfg_attr(test, derive(Debug))]
 pub struct AccountBalances<'a> {
     accounts_doc: &'a AccountsDocument,
@@ -25,22 +57,22 @@ pub struct AccountBalances<'a> {
 }
 
 impl<'a> Iterator for AccountBalances<'a> {
-    type Item = (&'a AccountId, Amount);
+    type Item = (&'a AccountId, Inventory);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let account = self.accounts_doc.accounts.get(self.position)?;
+        let account = loop {
+            let account = self.accounts_doc.accounts.get(self.position)?;
+            if account.closing_date.is_none() {
+                break account;
+            }
+            self.position += 1;
+        };
         let balance = self
             .accounts_doc
             .balance(&account.id)
             .expect("we know this account exists");
         self.position += 1;
-        Some((
-            &account.id,
-            Amount {
-                amount: balance,
-                currency: account.currency.clone(),
-            },
-        ))
+        Some((&account.id, balance))
     }
 }
 
@@ -60,7 +92,10 @@ pub struct RegularPosting {
 /// Conversion postings take the form:
 /// ```beancount
 /// Assets:BankCheckingEUR 100 EUR @ 0.8 GBP
+/// Assets:BankCheckingEUR 100 EUR @@ 85.13 GBP
 /// ```
+/// The first gives the rate and the second gives the total amount in the transaction currency,
+/// from which the rate is derived.
 #[derive(Debug)]
 pub struct ConversionPosting {
     pub(crate) account_id: AccountId,
@@ -68,14 +103,87 @@ pub struct ConversionPosting {
     pub(crate) account_currency: String,
     pub(crate) rate: Decimal,
     pub(crate) tx_currency: String,
+    pub(crate) total: Option<Decimal>, // the absolute total price, if given with `@@`
+}
+
+impl ConversionPosting {
+    /// Returns the rate used to convert the account currency into the transaction currency.
+    pub fn rate(&self) -> Decimal {
+        self.rate
+    }
+
+    /// Returns the total price, if the posting was declared with `@@`. The total is always
+    /// positive.
+    pub fn total(&self) -> Option<Decimal> {
+        self.total
+    }
+
+    /// Returns the amount in the transaction currency. This is exactly the total, with the sign of
+    /// the account amount, if there is one.
+    fn tx_amount(&self) -> Decimal {
+        match self.total {
+            Some(total) if self.account_amount.is_sign_negative() => -total,
+            Some(total) => total,
+            None => self.account_amount * self.rate,
+        }
+    }
+}
+
+/// Represents a posting which adds a lot held at cost to an account (an augmentation) or removes
+/// units from the lots held (a reduction). Cost postings take the form:
+/// ```beancount
+/// Assets:Broker     10 VWRL {85.20 GBP}
+/// Assets:Broker     -5 VWRL {} @ 95.00 GBP
+/// ```
+/// The transaction currency is the currency of the cost. When the transaction is added to the
+/// document reductions are matched against the lots in the account using the account's
+/// [`BookingMethod`]. The optional price of a reduction is the sale price of each unit, which is
+/// used to calculate the realized gain. A total price given with `@@` is divided by the number of
+/// units.
+#[derive(Debug)]
+pub struct CostPosting {
+    pub(crate) account_id: AccountId,
+    pub(crate) units: Decimal,
+    pub(crate) currency: String,
+    pub(crate) cost: CostSpec,
+    pub(crate) price: Option<Amount>,
+    pub(crate) booked: Vec<Position>, // filled in when the transaction is added
 }
 
-/// Represents the three different types of posting.
+impl CostPosting {
+    /// Returns the lots added to the account by the posting. A reduction is booked as one or more
+    /// lots with negative units.
+    pub fn booked(&self) -> &[Position] {
+        &self.booked
+    }
+
+    /// Books the posting against `inventory`, returning the lots to add to the account.
+    fn book(
+        &self,
+        inventory: &Inventory,
+        method: BookingMethod,
+        date: Date,
+    ) -> Result<Vec<Position>, BookingError> {
+        if self.units.is_sign_negative() {
+            inventory.book_reduction(self.units, &self.currency, &self.cost, method, date)
+        } else {
+            Ok(vec![Position::lot(
+                self.units,
+                &self.currency,
+                &self.cost,
+                date,
+            )?])
+        }
+    }
+}
+
+/// Represents the four different types of posting.
 #[derive(Debug)]
 pub enum Posting {
     Auto(AccountId),
     Regular(RegularPosting),
     Conversion(ConversionPosting),
+    Cost(CostPosting),
 }
 
 /// Posting information for [`Posting::Regular`] and [`Posting::Conversion`]
@@ -93,6 +201,18 @@ impl Posting {
             Posting::Auto(id) => id,
             Posting::Regular(posting) => &posting.account_id,
             Posting::Conversion(posting) => &posting.account_id,
+            Posting::Cost(posting) => &posting.account_id,
+        }
+    }
+
+    /// Returns the account currency unless the posting is an auto-posting in which case [`None`]
+    /// is returned.
+    pub fn account_currency(&self) -> Option<&str> {
+        match self {
+            Posting::Auto(_) => None,
+            Posting::Regular(posting) => Some(&posting.currency),
+            Posting::Conversion(posting) => Some(&posting.account_currency),
+            Posting::Cost(posting) => Some(&posting.currency),
         }
     }
 
@@ -103,28 +223,26 @@ impl Posting {
             Posting::Auto(_) => None,
             Posting::Regular(posting) => Some(posting.amount),
             Posting::Conversion(posting) => Some(posting.account_amount),
+            Posting::Cost(posting) => Some(posting.units),
         }
     }
 
     /// Returns  [`Some`] containing a [`PostingInfo`] for [`Posting::Regular`] and
-    /// [`Posting::Conversion`], Returns [`None`] for a [`Posting::Auto`].
+    /// [`Posting::Conversion`], Returns [`None`] for a [`Posting::Auto`] and a [`Posting::Cost`],
+    /// since the transaction amount of a cost posting depends on the lots it's booked against.
     fn info(&self) -> Option<PostingInfo> {
         match self {
-            Posting::Auto(_) => None,
+            Posting::Auto(_) | Posting::Cost(_) => None,
             Posting::Regular(posting) => Some(PostingInfo {
                 account_currency: posting.currency.clone(),
                 tx_amount: posting.amount,
                 tx_currency: posting.currency.clone(),
             }),
-            Posting::Conversion(posting) => {
-                let tx_amount = posting.account_amount * posting.rate;
-
-                Some(PostingInfo {
-                    account_currency: posting.account_currency.clone(),
-                    tx_amount,
-                    tx_currency: posting.tx_currency.clone(),
-                })
-            }
+            Posting::Conversion(posting) => Some(PostingInfo {
+                account_currency: posting.account_currency.clone(),
+                tx_amount: posting.tx_amount(),
+                tx_currency: posting.tx_currency.clone(),
+            }),
         }
     }
 }
@@ -135,14 +253,19 @@ impl Posting {
 /// 2. All conversion postings have the same conversion currency and the same currency as regular
 ///    postings.
 /// 3. There is at most one auto posting.
-/// 4. The account id in regular postings corresponds to an open account, with the same currency.
-/// 5. The account id in a conversion posting corresponds to an open account with the same (pre
+/// 4. The account id in regular postings corresponds to an open account, which allows the posting
+///    currency.
+/// 5. The account id in a conversion posting corresponds to an open account which allows the (pre
 ///    conversion) currency.
 /// 6. The account id in an auto-posting corresponds to an open account, If the transaction
-///    contains other postings then the currency of the account will be the same as the currency of
-///    all regular postings and the converted to currency of all conversion postings.
-/// 7. For transactions with no auto-posting the sum of all amounts (converted amounts in the case
-///    of conversion postings) will be zero i.e. the transaction will be balanced.
+///    contains other postings then the account will allow the currency of all regular postings
+///    and the converted to currency of all conversion postings.
+/// 7. The account id in a cost posting corresponds to an open account which allows the posting
+///    currency, the cost currency is the same as the currency of regular postings, and reductions
+///    have been booked against lots held in the account.
+/// 8. For transactions with no auto-posting the sum of all amounts (converted amounts in the case
+///    of conversion postings) will be zero, to within the tolerance of the transaction currency,
+///    i.e. the transaction will be balanced.
 ///
 /// It isn't possible to create a [`Transaction`] directly however the
 /// [`AccountsDocument::add_transaction`] method provides an indirect method of creating a
@@ -154,17 +277,58 @@ impl Posting {
 /// is required.
 #[derive(Debug)]
 pub struct Transaction {
-    _date: Date,
-    _description: String,
+    date: Date,
+    payee: Option<String>,
+    narration: String,
     balance: Decimal, // this can only be non zero if the transaction contains an auto-posting
+    currency: Option<String>, // this is only None if the transaction only has an auto-posting
     postings: Vec<Posting>, // use a vec to preserve the order
+    synthetic: bool,
+    realized_gain: Option<Decimal>,
 }
 
 impl Transaction {
-    /// For a given `account` returns the sum of all postings to that account in the account
-    /// currency. If there's an auto-posting to `account` then the posting amount is given by the
-    /// amount required to balance the transaction.
-    pub fn balance(&self, account: &AccountId) -> Option<Decimal> {
+    /// Returns the date of the transaction.
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    /// Returns the payee if the transaction was declared with both a payee and a narration e.g.
+    /// `2023-02-03 * "Payee" "Narration"`, otherwise returns [`None`].
+    pub fn payee(&self) -> Option<&str> {
+        self.payee.as_deref()
+    }
+
+    /// Returns the narration of the transaction.
+    pub fn narration(&self) -> &str {
+        &self.narration
+    }
+
+    /// Returns the postings of the transaction in the order they were added.
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    /// Returns the realized gain, in the transaction currency, of the reductions with a price
+    /// i.e. the proceeds of the sales minus the cost of the lots booked. Losses are negative.
+    /// Returns [`None`] if the transaction has no reductions with a price.
+    pub fn realized_gain(&self) -> Option<Amount> {
+        Some(Amount {
+            currency: self.currency.clone()?,
+            amount: self.realized_gain?,
+        })
+    }
+
+    /// Returns `true` if the transaction was created by the document rather than added with
+    /// [`AccountsDocument::add_transaction`] e.g. the padding transaction inserted for a [`Pad`].
+    pub fn is_synthetic(&self) -> bool {
+        self.synthetic
+    }
+
+    /// For a given `account` returns the sum of all postings to that account, with one amount per
+    /// account currency. If there's an auto-posting to `account` then the posting amount is given
+    /// by the amount required to balance the transaction, in the transaction currency.
+    pub fn balance(&self, account: &AccountId) -> Option<Inventory> {
         let filtered: Vec<&Posting> = self
             .postings
             .iter()
@@ -175,11 +339,96 @@ impl Transaction {
             return None;
         }
 
-        Some(filtered.iter().fold(Decimal::ZERO, |s, p| {
-            s + p.account_amount().unwrap_or_else(|| -self.balance) // NOTE: if there's an auto
-            // posting it's account_currency is guaranteed to be the same as the
-            // transaction_currency.
-        }))
+        let mut inventory = Inventory::new();
+        for posting in filtered {
+            if let Posting::Cost(posting) = posting {
+                for lot in &posting.booked {
+                    inventory.add_position(lot.clone());
+                }
+                continue;
+            }
+
+            match (posting.account_currency(), posting.account_amount()) {
+                (Some(currency), Some(amount)) => inventory.add(currency, amount),
+                // NOTE: an auto-posting is in the transaction currency. If there's no transaction
+                // currency then the auto-posting is the only posting and its amount is zero.
+                _ => {
+                    if let Some(currency) = &self.currency {
+                        inventory.add(currency, -self.balance);
+                    }
+                }
+            }
+        }
+
+        Some(inventory)
+    }
+}
+
+/// A balance assertion, which states that the balance of an account at the start of a day (i.e.
+/// excluding transactions on that day) is equal to an amount. Balance assertions take the form:
+/// ```beancount
+/// 2023-02-01 balance Assets:BankChecking 1234.56 GBP
+/// ```
+#[derive(Debug)]
+pub struct BalanceAssertion {
+    date: Date,
+    account_id: AccountId,
+    amount: Amount,
+}
+
+impl BalanceAssertion {
+    /// Returns the date of the assertion.
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    /// Returns the account the assertion applies to.
+    pub fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    /// Returns the asserted balance.
+    pub fn amount(&self) -> &Amount {
+        &self.amount
+    }
+}
+
+/// A pad directive, which inserts a transaction from `source_account_id` into `account_id` so that
+/// the next balance assertion for `account_id` in each currency holds. Pads take the form:
+/// ```beancount
+/// 2023-01-01 pad Assets:BankChecking Equity:Opening-Balances
+/// ```
+#[derive(Debug)]
+pub struct Pad {
+    date: Date,
+    account_id: AccountId,
+    source_account_id: AccountId,
+    // The currencies of the balance assertions which followed the pad, each of which is only
+    // padded by the first of them
+    currencies: Vec<String>,
+    used: bool,
+}
+
+impl Pad {
+    /// Returns the date of the pad, which is also the date of the padding transaction.
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    /// Returns the account which is padded.
+    pub fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    /// Returns the account the padding amount is taken from.
+    pub fn source_account_id(&self) -> &AccountId {
+        &self.source_account_id
+    }
+
+    /// Returns `true` if a padding transaction was inserted for a balance assertion which followed
+    /// the pad.
+    pub fn is_used(&self) -> bool {
+        self.used
     }
 }
 
@@ -189,6 +438,11 @@ impl Transaction {
 pub struct AccountsDocument {
     pub(crate) accounts: Vec<Account>, // use vector to preserve the read order
     transactions: Vec<Transaction>,
+    balance_assertions: Vec<BalanceAssertion>,
+    pads: Vec<Pad>,
+    prices: PriceDb,
+    gains_account: AccountId,
+    options: LedgerOptions,
 }
 
 #[allow(clippy::new_without_default)] // `new` is more idiomatic than `default` for initializing an empty `AccountsDocument`
@@ -197,9 +451,30 @@ impl AccountsDocument {
         AccountsDocument {
             accounts: vec![],
             transactions: vec![],
+            balance_assertions: vec![],
+            pads: vec![],
+            prices: PriceDb::new(),
+            gains_account: default_gains_account(),
+            options: LedgerOptions::default(),
         }
     }
 
+    /// Sets the account which realized gains are posted to when a transaction doesn't include
+    /// them, by default this is `Income:Gains`.
+    pub fn set_gains_account(&mut self, account_id: AccountId) {
+        self.gains_account = account_id;
+    }
+
+    /// Returns the options set with option lines.
+    pub fn options(&self) -> &LedgerOptions {
+        &self.options
+    }
+
+    /// Sets the option `name` to `value`, see [`LedgerOptions::set`].
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        self.options.set(name, value)
+    }
+
     /// Add an [`Account`] to the document. Returns an [`OpenAccountError`] if an account with the
     /// same [`AccountId`] already exists.
     pub fn open_an_account(&mut self, account: Account) -> Result<(), OpenAccountError> {
@@ -211,72 +486,479 @@ impl AccountsDocument {
         }
     }
 
+    /// Closes the account with id `account_id` on `date`. Transactions dated after `date` which
+    /// post to the account are rejected. Once the account is closed its balance at the end of
+    /// `date` is checked, and a [`CloseAccountError::NonZeroBalance`] is returned if it isn't zero,
+    /// though the account stays closed.
+    pub fn close_account(
+        &mut self,
+        date: Date,
+        account_id: &AccountId,
+    ) -> Result<(), CloseAccountError> {
+        let Some(account) = self.find_account(account_id) else {
+            return Err(CloseAccountError::AccountNotFound);
+        };
+        if account.closing_date.is_some() {
+            return Err(CloseAccountError::AccountAlreadyClosed);
+        }
+        if date < account.opening_date {
+            return Err(CloseAccountError::AccountNotOpen);
+        }
+
+        let account = self
+            .accounts
+            .iter_mut()
+            .find(|a| &a.id == account_id)
+            .expect("we know this account exists");
+        account.closing_date = Some(date);
+
+        let balance = self.balance_at_end_of(account_id, date);
+        if !balance.is_empty() {
+            return Err(CloseAccountError::NonZeroBalance(balance));
+        }
+
+        Ok(())
+    }
+
     /// Adds a [`Transaction`] to the document if the [`Transaction`] defined by the arguments is
-    /// valid.
+    /// valid. The `payee` is optional, matching the single string (narration only) and two string
+    /// (payee and narration) forms of a beancount transaction.
     pub fn add_transaction(
         &mut self,
         date: Date,
-        description: impl Into<String>,
+        payee: Option<String>,
+        narration: impl Into<String>,
         postings: Vec<Posting>,
     ) -> Result<(), AddTransactionError> {
+        self.try_add_transaction(date, payee, narration, postings)
+            .map_err(|(error, _)| error)
+    }
+
+    /// Same as [`AccountsDocument::add_transaction`] except that on error the index of the
+    /// [`Posting`] which caused the error is also returned. The index is [`None`] if the error
+    /// applies to the transaction as a whole e.g. [`AddTransactionError::NotBalanced`].
+    pub(crate) fn try_add_transaction(
+        &mut self,
+        date: Date,
+        payee: Option<String>,
+        narration: impl Into<String>,
+        mut postings: Vec<Posting>,
+    ) -> Result<(), (AddTransactionError, Option<usize>)> {
         let mut running_total = Decimal::ZERO;
-        let mut auto_posting: Option<(&Posting, &Account)> = None;
+        let mut auto_posting: Option<(usize, &Account)> = None;
         let mut currency: Option<String> = None;
-        for posting in &postings {
+        // The lots booked by each cost posting, and the inventories of the accounts they were
+        // booked against including any lots booked by earlier postings in the transaction.
+        let mut booked: Vec<(usize, Vec<Position>)> = vec![];
+        let mut inventories: HashMap<&AccountId, Inventory> = HashMap::new();
+        // The realized gain of reductions with a price, and the postings to the gains account
+        let mut realized_gain: Option<Decimal> = None;
+        let mut gains_postings: Vec<usize> = vec![];
+        let mut gains_total = Decimal::ZERO;
+        // The tolerance of each currency inferred from the precision of the posting amounts
+        let mut tolerances: HashMap<&str, Decimal> = HashMap::new();
+        for (index, posting) in postings.iter().enumerate() {
             let Some(account) = self.find_account(posting.account_id()) else {
-                return Err(AddTransactionError::AccountNotFound);
+                return Err((AddTransactionError::AccountNotFound, Some(index)));
             };
             if date < account.opening_date {
-                return Err(AddTransactionError::AccountNotOpen);
+                return Err((AddTransactionError::AccountNotOpen, Some(index)));
+            }
+            if account
+                .closing_date
+                .is_some_and(|closing_date| date > closing_date)
+            {
+                return Err((AddTransactionError::AccountClosed, Some(index)));
+            }
+
+            let post_info = if let Posting::Cost(posting) = posting {
+                if !account.allows(&posting.currency) {
+                    return Err((AddTransactionError::IncorrectAccountCurrency, Some(index)));
+                }
+
+                let inventory = inventories
+                    .entry(&account.id)
+                    .or_insert_with(|| self.balance_at_end_of(&account.id, date));
+                let lots = posting
+                    .book(inventory, account.booking_method, date)
+                    .map_err(|e| (AddTransactionError::Booking(e), Some(index)))?;
+                for lot in &lots {
+                    inventory.add_position(lot.clone());
+                }
+
+                let post_info = PostingInfo {
+                    account_currency: posting.currency.clone(),
+                    tx_amount: lots.iter().filter_map(Position::book_value).sum(),
+                    tx_currency: lots[0]
+                        .cost()
+                        .expect("booked lots are held at cost")
+                        .currency()
+                        .to_string(),
+                };
+
+                if let Some(price) = posting
+                    .price
+                    .as_ref()
+                    .filter(|_| posting.units < Decimal::ZERO)
+                {
+                    if price.currency != post_info.tx_currency {
+                        return Err((
+                            AddTransactionError::IncorrectTransactionCurrency,
+                            Some(index),
+                        ));
+                    }
+                    // The proceeds minus the cost, the transaction amount is minus the cost
+                    let gain = -posting.units * price.amount + post_info.tx_amount;
+                    *realized_gain.get_or_insert(Decimal::ZERO) += gain;
+                }
+
+                booked.push((index, lots));
+                Some(post_info)
+            } else {
+                posting.info()
+            };
+
+            if let (Some(currency), Some(tolerance)) = (
+                posting.account_currency(),
+                posting.account_amount().and_then(inferred_tolerance),
+            ) {
+                let inferred = tolerances.entry(currency).or_default();
+                *inferred = tolerance.max(*inferred);
             }
 
-            if let Some(post_info) = posting.info() {
+            if let Some(post_info) = post_info {
                 // We now know this posting isn't an auto-posting
-                if account.currency != post_info.account_currency {
-                    return Err(AddTransactionError::IncorrectAccountCurrency);
+                if !account.allows(&post_info.account_currency) {
+                    return Err((AddTransactionError::IncorrectAccountCurrency, Some(index)));
                 }
 
                 match &mut currency {
                     Some(c) if c == &post_info.tx_currency => c,
-                    Some(_) => return Err(AddTransactionError::IncorrectTransactionCurrency),
+                    Some(_) => {
+                        return Err((
+                            AddTransactionError::IncorrectTransactionCurrency,
+                            Some(index),
+                        ));
+                    }
                     None => currency.insert(post_info.tx_currency),
                 };
 
+                if posting.account_id() == &self.gains_account {
+                    gains_postings.push(index);
+                    gains_total += post_info.tx_amount;
+                }
+
                 running_total += post_info.tx_amount;
             } else {
                 if auto_posting.is_some() {
-                    return Err(AddTransactionError::MoreThanOneAutoPosting);
+                    return Err((AddTransactionError::MoreThanOneAutoPosting, Some(index)));
                 }
 
-                let _ = auto_posting.insert((posting, account));
+                let _ = auto_posting.insert((index, account));
             }
         }
 
-        if let Some((_, account)) = auto_posting {
-            // We have an auto-posting, we must check that the account posted to has the same
-            // currency as the transaction currency. If there is no transaction currency (which can
-            // happen if this is the only posting), then the transaction currency **is** the
-            // posting currency so all is good.
-            if currency.is_some_and(|c| c != account.currency) {
-                return Err(AddTransactionError::IncorrectTransactionCurrency);
+        // Transactions can be out of balance by the tolerance of the transaction currency, which is
+        // inferred from the amounts in that currency or else is the default for the currency.
+        let tolerance = currency.as_deref().map_or(Decimal::ZERO, |currency| {
+            tolerances
+                .get(currency)
+                .copied()
+                .unwrap_or_else(|| self.options.tolerance_defaults().get(currency))
+        });
+        let not_balanced = |residual| AddTransactionError::NotBalanced {
+            residual,
+            currency: currency.clone().unwrap_or_default(),
+            tolerance,
+        };
+
+        if let Some((index, account)) = auto_posting {
+            // We have an auto-posting, we must check that the account posted to allows the
+            // transaction currency. If there is no transaction currency (which can happen if this
+            // is the only posting), then the transaction currency **is** the posting currency so
+            // all is good.
+            if currency.as_ref().is_some_and(|c| !account.allows(c)) {
+                return Err((
+                    AddTransactionError::IncorrectTransactionCurrency,
+                    Some(index),
+                ));
+            }
+        } else if let Some(gain) = realized_gain {
+            if let Some(&index) = gains_postings.first() {
+                // The gains leg is given so check it matches the realized gain, income is negative
+                if (gains_total + gain).abs() > tolerance {
+                    return Err((AddTransactionError::IncorrectGain, Some(index)));
+                }
+                if running_total.abs() > tolerance {
+                    return Err((not_balanced(running_total), None));
+                }
+            } else if running_total.abs() > tolerance {
+                // Infer the gains leg, as long as it's all that's needed to balance the transaction
+                if (running_total - gain).abs() > tolerance {
+                    return Err((not_balanced(running_total), None));
+                }
+                let tx_currency = currency.clone().expect("there's a reduction with a price");
+                let gains_account_is_open =
+                    self.find_account(&self.gains_account).is_some_and(|a| {
+                        date >= a.opening_date
+                            && a.closing_date
+                                .is_none_or(|closing_date| date <= closing_date)
+                            && a.allows(&tx_currency)
+                    });
+                if !gains_account_is_open {
+                    return Err((AddTransactionError::GainsAccountNotOpen, None));
+                }
+
+                postings.push(Posting::Regular(RegularPosting {
+                    account_id: self.gains_account.clone(),
+                    amount: -gain,
+                    currency: tx_currency,
+                }));
+                running_total -= gain;
             }
         } else {
             // If there is no auto-posting then the tx must balance
-            if running_total.abs() > TOLERANCE {
-                return Err(AddTransactionError::NotBalanced);
+            if running_total.abs() > tolerance {
+                return Err((not_balanced(running_total), None));
             }
         };
 
+        for (index, lots) in booked {
+            if let Posting::Cost(posting) = &mut postings[index] {
+                posting.booked = lots;
+            }
+        }
+
         self.transactions.push(Transaction {
-            _date: date,
-            _description: description.into(),
+            date,
+            payee,
+            narration: narration.into(),
             balance: running_total,
+            currency,
             postings,
+            synthetic: false,
+            realized_gain,
         });
 
         Ok(())
     }
 
+    /// Checks that the balance of the account at the start of `date` i.e. the sum of all
+    /// transactions dated before `date`, is equal to `amount`. Following Beancount the balance
+    /// only needs to be equal to within a tolerance inferred from the precision of `amount`: one
+    /// unit of the last decimal place, so `1234.56 GBP` is satisfied by balances between 1234.55
+    /// and 1234.57. Amounts without a decimal point use the default tolerance for the currency, see
+    /// [`ToleranceDefaults`].
+    ///
+    /// The balance is calculated from the transactions added so far. If the most recent [`Pad`]
+    /// for the account dated before `date` hasn't been followed by an assertion in the currency of
+    /// `amount` and the balance doesn't already hold, then a synthetic transaction dated on the pad
+    /// date is added which makes up the difference, and the pad is used.
+    /// If the assertion holds it's added to the document, otherwise a [`BalanceAssertionError`] is
+    /// returned.
+    pub fn assert_balance(
+        &mut self,
+        date: Date,
+        account_id: AccountId,
+        amount: Amount,
+    ) -> Result<(), BalanceAssertionError> {
+        let Some(account) = self.find_account(&account_id) else {
+            return Err(BalanceAssertionError::AccountNotFound);
+        };
+        if date < account.opening_date {
+            return Err(BalanceAssertionError::AccountNotOpen);
+        }
+        if !account.allows(&amount.currency) {
+            return Err(BalanceAssertionError::IncorrectCurrency);
+        }
+
+        let tolerance = balance_tolerance(&amount, self.options.tolerance_defaults());
+        let mut actual = self.balance_before(&account_id, date).get(&amount.currency);
+        let mut difference = actual - amount.amount;
+
+        // Only the most recent pad is used, any earlier pads are left unused. The padding is taken
+        // from the source account so it must allow the currency.
+        let source_allows = |pad: &Pad| {
+            self.find_account(&pad.source_account_id)
+                .is_some_and(|a| a.allows(&amount.currency))
+        };
+        let pad_index = self
+            .pads
+            .iter()
+            .rposition(|p| p.account_id == account_id && p.date < date)
+            .filter(|index| !self.pads[*index].currencies.contains(&amount.currency))
+            .filter(|index| source_allows(&self.pads[*index]));
+        if let Some(pad) = pad_index.map(|index| &mut self.pads[index]) {
+            pad.currencies.push(amount.currency.clone());
+            if difference.abs() > tolerance {
+                pad.used = true;
+                let (pad_date, source_account_id) = (pad.date, pad.source_account_id.clone());
+                // The padding goes at the date of the pad, so the transactions stay in date order
+                let index = self.transactions.partition_point(|t| t.date <= pad_date);
+                self.transactions.insert(
+                    index,
+                    Transaction {
+                        date: pad_date,
+                        payee: None,
+                        narration: format!("Padding inserted for balance of {amount}"),
+                        balance: Decimal::ZERO,
+                        currency: Some(amount.currency.clone()),
+                        postings: vec![
+                            Posting::Regular(RegularPosting {
+                                account_id: account_id.clone(),
+                                amount: -difference,
+                                currency: amount.currency.clone(),
+                            }),
+                            Posting::Regular(RegularPosting {
+                                account_id: source_account_id,
+                                amount: difference,
+                                currency: amount.currency.clone(),
+                            }),
+                        ],
+                        synthetic: true,
+                        realized_gain: None,
+                    },
+                );
+                actual = amount.amount;
+                difference = Decimal::ZERO;
+            }
+        }
+
+        if difference.abs() > tolerance {
+            return Err(BalanceAssertionError::Failed {
+                currency: amount.currency,
+                expected: amount.amount,
+                actual,
+                difference,
+            });
+        }
+
+        self.balance_assertions.push(BalanceAssertion {
+            date,
+            account_id,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Adds a [`Pad`] for the account with id `account_id`, which is used by the next balance
+    /// assertion for the account. Both accounts must be open on `date` and have a currency in
+    /// common, otherwise a [`PadError`] is returned.
+    pub fn pad_account(
+        &mut self,
+        date: Date,
+        account_id: AccountId,
+        source_account_id: AccountId,
+    ) -> Result<(), PadError> {
+        let Some(account) = self.find_account(&account_id) else {
+            return Err(PadError::AccountNotFound);
+        };
+        if date < account.opening_date {
+            return Err(PadError::AccountNotOpen);
+        }
+        if account
+            .closing_date
+            .is_some_and(|closing_date| date > closing_date)
+        {
+            return Err(PadError::AccountClosed);
+        }
+
+        let Some(source_account) = self.find_account(&source_account_id) else {
+            return Err(PadError::SourceAccountNotFound);
+        };
+        if date < source_account.opening_date {
+            return Err(PadError::SourceAccountNotOpen);
+        }
+        if source_account
+            .closing_date
+            .is_some_and(|closing_date| date > closing_date)
+        {
+            return Err(PadError::SourceAccountClosed);
+        }
+        let has_common_currency = account.currencies.is_empty()
+            || account.currencies.iter().any(|c| source_account.allows(c));
+        if !has_common_currency {
+            return Err(PadError::NoCommonCurrency);
+        }
+
+        self.pads.push(Pad {
+            date,
+            account_id,
+            source_account_id,
+            currencies: vec![],
+            used: false,
+        });
+
+        Ok(())
+    }
+
+    /// Returns the pads in the order they were added.
+    pub fn pads(&self) -> &[Pad] {
+        &self.pads
+    }
+
+    /// Returns the pads for which no padding transaction was inserted.
+    pub fn unused_pads(&self) -> impl Iterator<Item = &Pad> {
+        self.pads.iter().filter(|p| !p.used)
+    }
+
+    /// Records that on `date` one unit of the `base` currency cost `price`. Returns a
+    /// [`PriceError`] if the price is in the `base` currency or isn't positive.
+    pub fn add_price(
+        &mut self,
+        date: Date,
+        base: impl Into<String>,
+        price: Amount,
+    ) -> Result<(), PriceError> {
+        let base = base.into();
+        if base == price.currency {
+            return Err(PriceError::SameCurrency);
+        }
+        if price.amount <= Decimal::ZERO {
+            return Err(PriceError::NotPositive);
+        }
+
+        self.prices.add_price(date, base, price);
+
+        Ok(())
+    }
+
+    /// Returns the [`PriceDb`] containing every price added to the document.
+    pub fn prices(&self) -> &PriceDb {
+        &self.prices
+    }
+
+    /// Returns the balance assertions in the order they were added.
+    pub fn balance_assertions(&self) -> &[BalanceAssertion] {
+        &self.balance_assertions
+    }
+
+    /// Returns the sum of all postings to `account` dated before `date`.
+    fn balance_before(&self, account: &AccountId, date: Date) -> Inventory {
+        self.sum_postings(account, |t| t.date < date)
+    }
+
+    /// Returns the sum of all postings to `account` dated on or before `date`.
+    fn balance_at_end_of(&self, account: &AccountId, date: Date) -> Inventory {
+        self.sum_postings(account, |t| t.date <= date)
+    }
+
+    /// Returns the sum of all postings to `account` in the transactions matching `filter`.
+    fn sum_postings(
+        &self,
+        account: &AccountId,
+        filter: impl Fn(&Transaction) -> bool,
+    ) -> Inventory {
+        let mut inventory = Inventory::new();
+        for transaction in self.transactions.iter().filter(|t| filter(t)) {
+            if let Some(balance) = transaction.balance(account) {
+                inventory.add_inventory(&balance);
+            }
+        }
+        inventory
+    }
+
     fn find_account(&self, account_id: &AccountId) -> Option<&Account> {
         self.accounts.iter().find(|a| &a.id == account_id)
     }
@@ -285,24 +967,48 @@ impl AccountsDocument {
         self.accounts.iter().any(|a| &a.id == account)
     }
 
-    /// Returns the balance of `account`it it exists otherwise returns [`None`].
-    pub fn balance(&self, account: &AccountId) -> Option<Decimal> {
+    /// Returns the transactions in the order they were added.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Returns the balance of `account`, with one amount per commodity, if it exists otherwise
+    /// returns [`None`].
+    pub fn balance(&self, account: &AccountId) -> Option<Inventory> {
         if !self.account_exists(account) {
             return None;
         };
 
-        Some(self.transactions.iter().fold(Decimal::ZERO, |s, t| {
-            s + t.balance(account).unwrap_or(Decimal::ZERO)
-        }))
+        Some(self.sum_postings(account, |_| true))
     }
 
     /// Returns an [`AccountBalances`] iterator over all accounts.
-    pub fn balances(&self) -> AccountBalances {
+    pub fn balances(&self) -> AccountBalances<'_> {
         AccountBalances {
             accounts_doc: self,
             position: 0,
         }
     }
+
+    /// Returns the balance of each account, as [`AccountsDocument::balances`] does, converted into
+    /// `currency` with the prices declared on or before `date`. The balance of an account holding
+    /// a commodity which can't be converted is a [`ConversionError`], see [`Inventory::convert`].
+    pub fn balances_in<'a>(
+        &'a self,
+        currency: &'a str,
+        date: Date,
+    ) -> impl Iterator<Item = (&'a AccountId, Result<Amount, ConversionError>)> {
+        self.balances()
+            .map(move |(id, balance)| (id, balance.convert(&self.prices, currency, date)))
+    }
+}
+
+/// Returns `Income:Gains`, the default account realized gains are posted to.
+fn default_gains_account() -> AccountId {
+    AccountId {
+        components: vec!["Gains".to_string()],
+        type_: AccountType::Income,
+    }
 }
 
 /// The error returned by [`AccountsDocument::add_transaction`].
@@ -312,14 +1018,92 @@ pub enum AddTransactionError {
     AccountNotFound,
     #[error("account not open")]
     AccountNotOpen,
+    #[error("account closed")]
+    AccountClosed,
     #[error("the postings have different transaction currencies")]
     IncorrectTransactionCurrency,
     #[error("the account currency is incorect")]
     IncorrectAccountCurrency,
-    #[error("transaction is not balanced")]
-    NotBalanced,
+    /// The postings sum to `residual`, which is more than the `tolerance` of the transaction
+    /// currency.
+    #[error(
+        "transaction is not balanced, it's off by {residual} {currency} (tolerance {tolerance})"
+    )]
+    NotBalanced {
+        residual: Decimal,
+        currency: String,
+        tolerance: Decimal,
+    },
     #[error("only one auto posting is allowed per transaction")]
     MoreThanOneAutoPosting,
+    #[error("{0}")]
+    Booking(BookingError),
+    #[error("the gains posting doesn't match the realized gain")]
+    IncorrectGain,
+    #[error("the gains account isn't open so the realized gain can't be posted to it")]
+    GainsAccountNotOpen,
+}
+
+/// The error returned by [`AccountsDocument::assert_balance`].
+#[derive(Error, Debug, PartialEq)]
+pub enum BalanceAssertionError {
+    #[error("account not found")]
+    AccountNotFound,
+    #[error("account not open")]
+    AccountNotOpen,
+    #[error("the currency is not the account currency")]
+    IncorrectCurrency,
+    #[error(
+        "balance assertion failed: expected {expected} {currency}, actual {actual} {currency} (difference {difference} {currency})"
+    )]
+    Failed {
+        currency: String,
+        expected: Decimal,
+        actual: Decimal,
+        /// The actual amount minus the expected amount
+        difference: Decimal,
+    },
+}
+
+/// The error returned by [`AccountsDocument::close_account`].
+#[derive(Error, Debug, PartialEq)]
+pub enum CloseAccountError {
+    #[error("account not found")]
+    AccountNotFound,
+    #[error("account not open")]
+    AccountNotOpen,
+    #[error("account already closed")]
+    AccountAlreadyClosed,
+    #[error("account has a non-zero balance of {0}")]
+    NonZeroBalance(Inventory),
+}
+
+/// The error returned by [`AccountsDocument::pad_account`].
+#[derive(Error, Debug, PartialEq)]
+pub enum PadError {
+    #[error("account not found")]
+    AccountNotFound,
+    #[error("account not open")]
+    AccountNotOpen,
+    #[error("account closed")]
+    AccountClosed,
+    #[error("source account not found")]
+    SourceAccountNotFound,
+    #[error("source account not open")]
+    SourceAccountNotOpen,
+    #[error("source account closed")]
+    SourceAccountClosed,
+    #[error("the accounts have no currency in common")]
+    NoCommonCurrency,
+}
+
+/// The error returned by [`AccountsDocument::add_price`].
+#[derive(Error, Debug, PartialEq)]
+pub enum PriceError {
+    #[error("the price is in the currency being priced")]
+    SameCurrency,
+    #[error("the price must be positive")]
+    NotPositive,
 }
 
 /// The error returned by [`AccountsDocument::open_an_account`].
@@ -340,11 +1124,11 @@ mod tests {
     fn balance_works() {
         let accounts = accounts_doc();
         let sum = accounts.balance(&AccountId {
-            name: "AccountA".to_string(),
+            components: vec!["AccountA".to_string()],
             type_: AccountType::Income,
         });
 
-        assert_eq!(sum, Some(100.into()));
+        assert_eq!(sum, Some(inventory(100, "GBP")));
     }
 
     #[test]
@@ -356,13 +1140,10 @@ mod tests {
             balances.next(),
             Some((
                 &AccountId {
-                    name: "AccountA".to_string(),
+                    components: vec!["AccountA".to_string()],
                     type_: AccountType::Income,
                 },
-                Amount {
-                    amount: 100.into(),
-                    currency: "GBP".to_string(),
-                }
+                inventory(100, "GBP")
             ))
         );
 
@@ -370,13 +1151,10 @@ mod tests {
             balances.next(),
             Some((
                 &AccountId {
-                    name: "AccountB".to_string(),
+                    components: vec!["AccountB".to_string()],
                     type_: AccountType::Income,
                 },
-                Amount {
-                    amount: (-50).into(),
-                    currency: "USD".to_string(),
-                }
+                inventory(-50, "USD")
             ))
         );
 
@@ -384,13 +1162,10 @@ mod tests {
             balances.next(),
             Some((
                 &AccountId {
-                    name: "AccountC".to_string(),
+                    components: vec!["AccountC".to_string()],
                     type_: AccountType::Income,
                 },
-                Amount {
-                    amount: (0).into(),
-                    currency: "GBP".to_string(),
-                }
+                Inventory::new()
             ))
         );
 
@@ -398,40 +1173,91 @@ mod tests {
             balances.next(),
             Some((
                 &AccountId {
-                    name: "AccountD".to_string(),
+                    components: vec!["AccountD".to_string()],
                     type_: AccountType::Income,
                 },
-                Amount {
-                    amount: (-50).into(),
-                    currency: "GBP".to_string(),
-                }
+                inventory(-50, "GBP")
             ))
         );
 
         assert_eq!(balances.next(), None);
     }
 
+    #[test]
+    fn nested_accounts_are_distinct() {
+        // Accounts which share a prefix are different accounts.
+        let mut doc = AccountsDocument::new();
+        doc.open_an_account(Account {
+            id: AccountId {
+                components: vec!["Bank".to_string(), "Checking".to_string()],
+                type_: AccountType::Asset,
+            },
+            currencies: vec!["GBP".to_string()],
+            opening_date: date! {2012-01-04},
+            closing_date: None,
+            booking_method: BookingMethod::Strict,
+        })
+        .expect("this is the first account so this won't fail");
+        doc.open_an_account(Account {
+            id: AccountId {
+                components: vec![
+                    "Bank".to_string(),
+                    "Checking".to_string(),
+                    "Joint".to_string(),
+                ],
+                type_: AccountType::Asset,
+            },
+            currencies: vec!["GBP".to_string()],
+            opening_date: date! {2012-01-04},
+            closing_date: None,
+            booking_method: BookingMethod::Strict,
+        })
+        .expect("the account has an extra component so this won't fail");
+
+        let err = doc
+            .open_an_account(Account {
+                id: AccountId {
+                    components: vec![
+                        "Bank".to_string(),
+                        "Checking".to_string(),
+                        "Joint".to_string(),
+                    ],
+                    type_: AccountType::Asset,
+                },
+                currencies: vec!["GBP".to_string()],
+                opening_date: date! {2012-01-04},
+                closing_date: None,
+                booking_method: BookingMethod::Strict,
+            })
+            .unwrap_err();
+        assert_eq!(err, OpenAccountError::AccountAlreadyExists);
+    }
+
     #[test]
     fn open_an_account_works() {
         accounts_doc()
             .open_an_account(Account {
                 id: AccountId {
-                    name: "AccountA".to_string(),
+                    components: vec!["AccountA".to_string()],
                     type_: AccountType::Asset,
                 },
-                currency: "GBP".to_string(),
+                currencies: vec!["GBP".to_string()],
                 opening_date: date! {2012-01-04},
+                closing_date: None,
+                booking_method: BookingMethod::Strict,
             })
             .expect("there is no acount with the same AccountId so this won't fail");
 
         let err = accounts_doc()
             .open_an_account(Account {
                 id: AccountId {
-                    name: "AccountA".to_string(),
+                    components: vec!["AccountA".to_string()],
                     type_: AccountType::Income,
                 },
-                currency: "GBP".to_string(),
+                currencies: vec!["GBP".to_string()],
                 opening_date: date! {2012-01-04},
+                closing_date: None,
+                booking_method: BookingMethod::Strict,
             })
             .unwrap_err();
 
@@ -456,11 +1282,12 @@ mod tests {
         accounts_doc()
             .add_transaction(
                 date! {2012-05-13},
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
@@ -468,13 +1295,14 @@ mod tests {
                     }),
                     Posting::Conversion(ConversionPosting {
                         account_id: AccountId {
-                            name: "AccountB".to_string(),
+                            components: vec!["AccountB".to_string()],
                             type_: AccountType::Income,
                         },
                         account_amount: (-100_i8).into(),
                         account_currency: "USD".to_string(),
                         tx_currency: "GBP".into(),
                         rate: 1.into(),
+                        total: None,
                     }),
                 ],
             )
@@ -483,11 +1311,12 @@ mod tests {
         let mut doc = accounts_doc();
         doc.add_transaction(
             date! {2012-05-13},
+            None,
             "Another Tx",
             vec![
                 Posting::Regular(RegularPosting {
                     account_id: AccountId {
-                        name: "AccountA".to_string(),
+                        components: vec!["AccountA".to_string()],
                         type_: AccountType::Income,
                     },
                     amount: 100.into(),
@@ -495,16 +1324,17 @@ mod tests {
                 }),
                 Posting::Conversion(ConversionPosting {
                     account_id: AccountId {
-                        name: "AccountB".to_string(),
+                        components: vec!["AccountB".to_string()],
                         type_: AccountType::Income,
                     },
                     account_amount: (-50_i8).into(),
                     account_currency: "USD".to_string(),
                     tx_currency: "GBP".into(),
                     rate: 1.into(),
+                    total: None,
                 }),
                 Posting::Auto(AccountId {
-                    name: "AccountD".to_string(),
+                    components: vec!["AccountD".to_string()],
                     type_: AccountType::Income,
                 }),
             ],
@@ -512,20 +1342,21 @@ mod tests {
         .expect("won't return an error");
         assert_eq!(
             doc.balance(&AccountId {
-                name: "AccountD".to_string(),
+                components: vec!["AccountD".to_string()],
                 type_: AccountType::Income,
             }),
-            Some((-100).into()) // -100 because the balance was -50 before we added this tx
+            Some(inventory(-100, "GBP")) // -100 because the balance was -50 before we added this tx
         );
 
         let err = accounts_doc()
             .add_transaction(
                 date! {2012-05-13},
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
@@ -533,7 +1364,7 @@ mod tests {
                     }),
                     Posting::Conversion(ConversionPosting {
                         account_id: AccountId {
-                            name: "AccountB".to_string(),
+                            components: vec!["AccountB".to_string()],
                             type_: AccountType::Asset, // There is no account named "AccountB" with
                                                        // type Asset (there is an "AccountB" with type Income).
                         },
@@ -541,6 +1372,7 @@ mod tests {
                         account_currency: "USD".to_string(),
                         tx_currency: "GBP".into(),
                         rate: 1.into(),
+                        total: None,
                     }),
                 ],
             )
@@ -550,11 +1382,12 @@ mod tests {
         let err = accounts_doc()
             .add_transaction(
                 date! {2012-04-11}, // this is before the accounts were opened
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
@@ -562,13 +1395,14 @@ mod tests {
                     }),
                     Posting::Conversion(ConversionPosting {
                         account_id: AccountId {
-                            name: "AccountB".to_string(),
+                            components: vec!["AccountB".to_string()],
                             type_: AccountType::Income,
                         },
                         account_amount: (-100_i8).into(),
                         account_currency: "USD".to_string(),
                         tx_currency: "GBP".into(),
                         rate: 1.into(),
+                        total: None,
                     }),
                 ],
             )
@@ -578,11 +1412,12 @@ mod tests {
         let err = accounts_doc()
             .add_transaction(
                 date! {2012-05-13},
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
@@ -590,13 +1425,14 @@ mod tests {
                     }),
                     Posting::Conversion(ConversionPosting {
                         account_id: AccountId {
-                            name: "AccountB".to_string(),
+                            components: vec!["AccountB".to_string()],
                             type_: AccountType::Income,
                         },
                         account_amount: (-100_i8).into(),
                         account_currency: "USD".to_string(),
                         tx_currency: "EUR".into(),
                         rate: 1.into(),
+                        total: None,
                     }),
                 ],
             )
@@ -606,11 +1442,12 @@ mod tests {
         let err = accounts_doc()
             .add_transaction(
                 date! {2012-05-13},
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
@@ -618,7 +1455,7 @@ mod tests {
                     }),
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountB".to_string(),
+                            components: vec!["AccountB".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: (-100_i8).into(),
@@ -632,11 +1469,12 @@ mod tests {
         let err = accounts_doc()
             .add_transaction(
                 date! {2012-05-13},
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
@@ -644,38 +1482,47 @@ mod tests {
                     }),
                     Posting::Conversion(ConversionPosting {
                         account_id: AccountId {
-                            name: "AccountB".to_string(),
+                            components: vec!["AccountB".to_string()],
                             type_: AccountType::Income,
                         },
                         account_amount: (-100_i8).into(),
                         account_currency: "USD".to_string(),
                         tx_currency: "GBP".into(),
                         rate: dec!(1.1),
+                        total: None,
                     }),
                 ],
             )
             .unwrap_err();
-        assert_eq!(err, AddTransactionError::NotBalanced);
+        assert_eq!(
+            err,
+            AddTransactionError::NotBalanced {
+                residual: dec!(-10),
+                currency: "GBP".to_string(),
+                tolerance: Decimal::ZERO,
+            }
+        );
 
         let err = accounts_doc()
             .add_transaction(
                 date! {2012-05-13},
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
                         currency: "GBP".to_string(),
                     }),
                     Posting::Auto(AccountId {
-                        name: "AccountC".to_string(),
+                        components: vec!["AccountC".to_string()],
                         type_: AccountType::Income,
                     }),
                     Posting::Auto(AccountId {
-                        name: "AccountD".to_string(),
+                        components: vec!["AccountD".to_string()],
                         type_: AccountType::Income,
                     }),
                 ],
@@ -684,6 +1531,271 @@ mod tests {
         assert_eq!(err, AddTransactionError::MoreThanOneAutoPosting);
     }
 
+    #[test]
+    fn assert_balance_works() {
+        let account_d = AccountId {
+            components: vec!["AccountD".to_string()],
+            type_: AccountType::Income,
+        };
+
+        // The balance of AccountD is -50 GBP from 2012-04-21 onwards.
+        let mut doc = accounts_doc();
+        doc.assert_balance(
+            date! {2012-04-21},
+            account_d.clone(),
+            Amount {
+                amount: 0.into(),
+                currency: "GBP".to_string(),
+            },
+        )
+        .expect("the transaction on the day of the assertion isn't included");
+        doc.assert_balance(
+            date! {2012-04-22},
+            account_d.clone(),
+            Amount {
+                amount: dec!(-50.01),
+                currency: "GBP".to_string(),
+            },
+        )
+        .expect("the difference is within the tolerance");
+        assert_eq!(doc.balance_assertions().len(), 2);
+
+        let err = doc
+            .assert_balance(
+                date! {2012-04-22},
+                account_d.clone(),
+                Amount {
+                    amount: dec!(-50.2),
+                    currency: "GBP".to_string(),
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            err,
+            BalanceAssertionError::Failed {
+                currency: "GBP".to_string(),
+                expected: dec!(-50.2),
+                actual: (-50).into(),
+                difference: dec!(0.2),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "balance assertion failed: expected -50.2 GBP, actual -50 GBP (difference 0.2 GBP)"
+        );
+
+        let err = doc
+            .assert_balance(
+                date! {2012-04-22},
+                account_d.clone(),
+                Amount {
+                    amount: (-50).into(),
+                    currency: "USD".to_string(),
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err, BalanceAssertionError::IncorrectCurrency);
+
+        let err = doc
+            .assert_balance(
+                date! {2012-04-11},
+                account_d,
+                Amount {
+                    amount: 0.into(),
+                    currency: "GBP".to_string(),
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err, BalanceAssertionError::AccountNotOpen);
+    }
+
+    #[test]
+    fn close_account_works() {
+        let account_a = AccountId {
+            components: vec!["AccountA".to_string()],
+            type_: AccountType::Income,
+        };
+        let account_d = AccountId {
+            components: vec!["AccountD".to_string()],
+            type_: AccountType::Income,
+        };
+
+        let mut doc = accounts_doc();
+        assert_eq!(
+            doc.close_account(date! {2012-04-11}, &account_a),
+            Err(CloseAccountError::AccountNotOpen)
+        );
+        // The account is closed even though its balance isn't zero
+        assert_eq!(
+            doc.close_account(date! {2012-04-21}, &account_d),
+            Err(CloseAccountError::NonZeroBalance(inventory(-50, "GBP")))
+        );
+        assert_eq!(
+            doc.close_account(date! {2012-04-20}, &account_d),
+            Err(CloseAccountError::AccountAlreadyClosed)
+        );
+        assert_eq!(doc.balances().count(), 3);
+
+        let err = doc
+            .add_transaction(
+                date! {2012-04-22},
+                None,
+                "After the close",
+                vec![
+                    Posting::Regular(RegularPosting {
+                        account_id: account_a,
+                        amount: 100.into(),
+                        currency: "GBP".to_string(),
+                    }),
+                    Posting::Auto(account_d),
+                ],
+            )
+            .unwrap_err();
+        assert_eq!(err, AddTransactionError::AccountClosed);
+    }
+
+    #[test]
+    fn pad_works() {
+        let account_a = AccountId {
+            components: vec!["AccountA".to_string()],
+            type_: AccountType::Income,
+        };
+        let account_d = AccountId {
+            components: vec!["AccountD".to_string()],
+            type_: AccountType::Income,
+        };
+
+        let mut doc = accounts_doc();
+        assert_eq!(
+            doc.pad_account(
+                date! {2012-04-13},
+                account_d.clone(),
+                AccountId {
+                    components: vec!["AccountB".to_string()],
+                    type_: AccountType::Income,
+                }
+            ),
+            Err(PadError::NoCommonCurrency)
+        );
+        doc.pad_account(date! {2012-04-13}, account_d.clone(), account_a.clone())
+            .expect("both accounts are open GBP accounts");
+        assert_eq!(doc.unused_pads().count(), 1);
+
+        // AccountD is -50 GBP after the transaction on 2012-04-21 so 150 GBP is padded in.
+        doc.assert_balance(
+            date! {2012-04-22},
+            account_d.clone(),
+            Amount {
+                amount: 100.into(),
+                currency: "GBP".to_string(),
+            },
+        )
+        .expect("the pad makes the assertion hold");
+        assert_eq!(doc.unused_pads().count(), 0);
+        assert_eq!(doc.balance(&account_d), Some(inventory(100, "GBP")));
+        assert_eq!(doc.balance(&account_a), Some(inventory(-50, "GBP")));
+
+        // The padding goes before the transaction on 2012-04-21, which is between the pad and the
+        // assertion
+        assert_eq!(
+            doc.transactions()
+                .iter()
+                .map(|t| (t.date(), t.is_synthetic()))
+                .collect::<Vec<_>>(),
+            vec![(date! {2012-04-13}, true), (date! {2012-04-21}, false)]
+        );
+
+        // The pad has been used so it doesn't apply to later assertions
+        let err = doc
+            .assert_balance(
+                date! {2012-04-23},
+                account_d,
+                Amount {
+                    amount: 0.into(),
+                    currency: "GBP".to_string(),
+                },
+            )
+            .unwrap_err();
+        assert!(matches!(err, BalanceAssertionError::Failed { .. }));
+    }
+
+    #[test]
+    fn balances_in_works() {
+        let account = |type_, name: &str| AccountId {
+            components: vec![name.to_string()],
+            type_,
+        };
+        let euros = account(AccountType::Asset, "Euros");
+        let dollars = account(AccountType::Asset, "Dollars");
+        let opening = account(AccountType::Equity, "Opening");
+        let amount = |amount: i64, currency: &str| Amount {
+            amount: amount.into(),
+            currency: currency.to_string(),
+        };
+
+        let mut doc = AccountsDocument::new();
+        for id in [&euros, &dollars, &opening] {
+            doc.open_an_account(Account {
+                id: id.clone(),
+                currencies: vec![],
+                opening_date: date! {2023-02-01},
+                closing_date: None,
+                booking_method: BookingMethod::Strict,
+            })
+            .expect("each account is only opened once");
+        }
+        for (id, currency) in [(&euros, "EUR"), (&dollars, "USD")] {
+            doc.add_transaction(
+                date! {2023-02-01},
+                None,
+                "Opening balance",
+                vec![
+                    Posting::Regular(RegularPosting {
+                        account_id: id.clone(),
+                        amount: 100.into(),
+                        currency: currency.to_string(),
+                    }),
+                    Posting::Regular(RegularPosting {
+                        account_id: opening.clone(),
+                        amount: (-100).into(),
+                        currency: currency.to_string(),
+                    }),
+                ],
+            )
+            .expect("the transaction balances");
+        }
+        doc.add_price(date! {2023-02-01}, "EUR", amount(2, "GBP"))
+            .expect("the price is valid");
+        doc.add_price(date! {2023-02-03}, "GBP", amount(4, "USD"))
+            .expect("the price is valid");
+
+        let no_rate = |currencies: &[&str]| {
+            Err(ConversionError::NoRate {
+                currencies: currencies.iter().map(|c| c.to_string()).collect(),
+                currency: "GBP".to_string(),
+            })
+        };
+        assert_eq!(
+            doc.balances_in("GBP", date! {2023-02-02})
+                .collect::<Vec<_>>(),
+            vec![
+                (&euros, Ok(amount(200, "GBP"))),
+                (&dollars, no_rate(&["USD"])),
+                (&opening, no_rate(&["USD"])),
+            ]
+        );
+        // The price of GBP in USD is inverted to convert USD into GBP
+        assert_eq!(
+            doc.balances_in("GBP", date! {2023-02-03})
+                .collect::<Vec<_>>(),
+            vec![
+                (&euros, Ok(amount(200, "GBP"))),
+                (&dollars, Ok(amount(25, "GBP"))),
+                (&opening, Ok(amount(-225, "GBP"))),
+            ]
+        );
+    }
+
     #[test]
     fn auto_posting_incorrect_currency() {
         // An auto-posting account must have the same currency as the transaction currency
@@ -691,18 +1803,19 @@ mod tests {
         let err = accounts_doc()
             .add_transaction(
                 date! {2012-05-13},
+                None,
                 "Another Tx",
                 vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
                         currency: "GBP".to_string(),
                     }),
                     Posting::Auto(AccountId {
-                        name: "AccountB".to_string(),
+                        components: vec!["AccountB".to_string()],
                         type_: AccountType::Income,
                     }),
                 ],
@@ -711,50 +1824,67 @@ mod tests {
         assert_eq!(err, AddTransactionError::IncorrectTransactionCurrency);
     }
 
+    fn inventory(amount: impl Into<Decimal>, currency: &str) -> Inventory {
+        Inventory::from_iter([Amount {
+            amount: amount.into(),
+            currency: currency.to_string(),
+        }])
+    }
+
     fn accounts_doc() -> AccountsDocument {
         AccountsDocument {
             accounts: vec![
                 Account {
                     id: AccountId {
-                        name: "AccountA".to_string(),
+                        components: vec!["AccountA".to_string()],
                         type_: AccountType::Income,
                     },
                     opening_date: date! {2012-04-12},
-                    currency: "GBP".to_string(),
+                    closing_date: None,
+                    booking_method: BookingMethod::Strict,
+                    currencies: vec!["GBP".to_string()],
                 },
                 Account {
                     id: AccountId {
-                        name: "AccountB".to_string(),
+                        components: vec!["AccountB".to_string()],
                         type_: AccountType::Income,
                     },
                     opening_date: date! {2012-04-12},
-                    currency: "USD".to_string(),
+                    closing_date: None,
+                    booking_method: BookingMethod::Strict,
+                    currencies: vec!["USD".to_string()],
                 },
                 Account {
                     id: AccountId {
-                        name: "AccountC".to_string(),
+                        components: vec!["AccountC".to_string()],
                         type_: AccountType::Income,
                     },
                     opening_date: date! {2012-04-12},
-                    currency: "GBP".to_string(),
+                    closing_date: None,
+                    booking_method: BookingMethod::Strict,
+                    currencies: vec!["GBP".to_string()],
                 },
                 Account {
                     id: AccountId {
-                        name: "AccountD".to_string(),
+                        components: vec!["AccountD".to_string()],
                         type_: AccountType::Income,
                     },
                     opening_date: date! {2012-04-12},
-                    currency: "GBP".to_string(),
+                    closing_date: None,
+                    booking_method: BookingMethod::Strict,
+                    currencies: vec!["GBP".to_string()],
                 },
             ],
             transactions: vec![Transaction {
-                _date: date! {2012-04-21},
-                _description: "transaction 1".to_string(),
+                date: date! {2012-04-21},
+                payee: None,
+                narration: "transaction 1".to_string(),
                 balance: (50).into(),
+                currency: Some("GBP".to_string()),
                 postings: vec![
                     Posting::Regular(RegularPosting {
                         account_id: AccountId {
-                            name: "AccountA".to_string(),
+                            components: vec!["AccountA".to_string()],
                             type_: AccountType::Income,
                         },
                         amount: 100.into(),
@@ -762,20 +1892,28 @@ mod tests {
                     }),
                     Posting::Conversion(ConversionPosting {
                         account_id: AccountId {
-                            name: "AccountB".to_string(),
+                            components: vec!["AccountB".to_string()],
                             type_: AccountType::Income,
                         },
                         account_amount: (-50).into(),
                         account_currency: "USD".to_string(),
                         rate: Decimal::ONE,
+                        total: None,
                         tx_currency: "GBP".to_string(),
                     }),
                     Posting::Auto(AccountId {
-                        name: "AccountD".to_string(),
+                        components: vec!["AccountD".to_string()],
                         type_: AccountType::Income,
                     }),
                 ],
+                synthetic: false,
+                realized_gain: None,
             }],
+            balance_assertions: vec![],
+            pads: vec![],
+            prices: PriceDb::new(),
+            gains_account: default_gains_account(),
+            options: LedgerOptions::default(),
         }
     }
 }
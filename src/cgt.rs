@@ -0,0 +1,598 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use date::{Date, interval::DateInterval};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::accounts_doc::{AccountsDocument, Posting};
+
+/// Capital gains are computed in sterling. Costs and proceeds in other currencies are converted
+/// using the prices in the document.
+const STERLING: &str = "GBP";
+
+/// The number of days after a disposal in which an acquisition is matched with it by the
+/// bed and breakfast rule.
+const BED_AND_BREAKFAST_DAYS: i32 = 30;
+
+/// A UK tax year, which runs from 6 April to 5 April the following year. A tax year is identified
+/// by the year it starts in and is displayed in the form `2023/24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaxYear(i16);
+
+impl TaxYear {
+    /// Returns the tax year which starts on 6 April of `year`.
+    pub fn starting_in(year: i16) -> TaxYear {
+        TaxYear(year)
+    }
+
+    /// Returns the tax year containing `date`.
+    pub fn containing(date: Date) -> TaxYear {
+        if (date.month(), date.day()) >= (4, 6) {
+            TaxYear(date.year())
+        } else {
+            TaxYear(date.year() - 1)
+        }
+    }
+
+    /// Returns the first day of the tax year.
+    pub fn start(&self) -> Date {
+        Date::new(self.0, 4, 6)
+    }
+
+    /// Returns the last day of the tax year.
+    pub fn end(&self) -> Date {
+        Date::new(self.0 + 1, 4, 5)
+    }
+}
+
+impl Display for TaxYear {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{:02}", self.0, (self.0 + 1).rem_euclid(100))
+    }
+}
+
+/// Units of a commodity along with their total allowable cost in sterling.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Holding {
+    units: Decimal,
+    cost: Decimal,
+}
+
+impl Holding {
+    /// Returns the number of units held.
+    pub fn units(&self) -> Decimal {
+        self.units
+    }
+
+    /// Returns the total allowable cost of the units held.
+    pub fn cost(&self) -> Decimal {
+        self.cost
+    }
+
+    fn add(&mut self, units: Decimal, cost: Decimal) {
+        self.units += units;
+        self.cost += cost;
+    }
+
+    /// Removes `units` from the holding, returning their share of the allowable cost.
+    fn take(&mut self, units: Decimal) -> Decimal {
+        let cost = if units == self.units {
+            self.cost
+        } else {
+            self.cost * units / self.units
+        };
+        self.units -= units;
+        self.cost -= cost;
+        cost
+    }
+}
+
+/// The rule used to match part of a disposal with the shares it's treated as disposing of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchingRule {
+    /// Matched with acquisitions on the day of the disposal.
+    SameDay,
+    /// Matched with acquisitions on the given date, within 30 days after the disposal.
+    BedAndBreakfast(Date),
+    /// Matched with the Section 104 pool, at the average cost of the shares in the pool.
+    Section104,
+}
+
+/// Part of a disposal matched by one [`MatchingRule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    rule: MatchingRule,
+    units: Decimal,
+    allowable_cost: Decimal,
+}
+
+impl Match {
+    /// Returns the rule used to match the units.
+    pub fn rule(&self) -> MatchingRule {
+        self.rule
+    }
+
+    /// Returns the number of units matched.
+    pub fn units(&self) -> Decimal {
+        self.units
+    }
+
+    /// Returns the allowable cost of the units matched.
+    pub fn allowable_cost(&self) -> Decimal {
+        self.allowable_cost
+    }
+}
+
+/// All the disposals of a commodity on one day, which are treated as a single disposal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disposal {
+    date: Date,
+    commodity: String,
+    units: Decimal,
+    proceeds: Decimal,
+    matches: Vec<Match>,
+}
+
+impl Disposal {
+    /// Returns the date of the disposal.
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    /// Returns the commodity disposed of.
+    pub fn commodity(&self) -> &str {
+        &self.commodity
+    }
+
+    /// Returns the number of units disposed of.
+    pub fn units(&self) -> Decimal {
+        self.units
+    }
+
+    /// Returns the proceeds of the disposal.
+    pub fn proceeds(&self) -> Decimal {
+        self.proceeds
+    }
+
+    /// Returns the parts of the disposal in the order they were matched.
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    /// Returns the allowable cost of all the units disposed of.
+    pub fn allowable_cost(&self) -> Decimal {
+        self.matches.iter().map(|m| m.allowable_cost).sum()
+    }
+
+    /// Returns the proceeds minus the allowable cost. Losses are negative.
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.allowable_cost()
+    }
+}
+
+impl Display for Disposal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}: proceeds {} {STERLING}, allowable cost {} {STERLING}, gain {} {STERLING}",
+            self.date,
+            self.units,
+            self.commodity,
+            self.proceeds,
+            self.allowable_cost(),
+            self.gain()
+        )
+    }
+}
+
+/// The disposals made in a tax year, in date order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaxYearReport {
+    year: TaxYear,
+    disposals: Vec<Disposal>,
+}
+
+impl TaxYearReport {
+    /// Returns the tax year.
+    pub fn year(&self) -> TaxYear {
+        self.year
+    }
+
+    /// Returns the disposals made in the tax year.
+    pub fn disposals(&self) -> &[Disposal] {
+        &self.disposals
+    }
+
+    /// Returns the total proceeds of all the disposals.
+    pub fn proceeds(&self) -> Decimal {
+        self.disposals.iter().map(Disposal::proceeds).sum()
+    }
+
+    /// Returns the total allowable cost of all the disposals.
+    pub fn allowable_cost(&self) -> Decimal {
+        self.disposals.iter().map(Disposal::allowable_cost).sum()
+    }
+
+    /// Returns the sum of the gains of the disposals which made a gain.
+    pub fn gains(&self) -> Decimal {
+        self.disposals
+            .iter()
+            .map(Disposal::gain)
+            .filter(|gain| gain.is_sign_positive())
+            .sum()
+    }
+
+    /// Returns the sum of the losses of the disposals which made a loss, as a positive number.
+    pub fn losses(&self) -> Decimal {
+        self.disposals
+            .iter()
+            .map(|disposal| -disposal.gain())
+            .filter(|loss| loss.is_sign_positive())
+            .sum()
+    }
+}
+
+impl Display for TaxYearReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Tax year {}", self.year)?;
+        for disposal in &self.disposals {
+            writeln!(f, "  {}", disposal)?;
+        }
+        write!(
+            f,
+            "  Total: proceeds {} {STERLING}, allowable cost {} {STERLING}, gains {} {STERLING}, \
+             losses {} {STERLING}",
+            self.proceeds(),
+            self.allowable_cost(),
+            self.gains(),
+            self.losses()
+        )
+    }
+}
+
+/// The acquisitions and disposals of a commodity on one day.
+#[derive(Debug, Default)]
+struct Day {
+    acquired: Holding, // reduced as acquisitions are matched with disposals
+    disposed: Decimal,
+    unmatched: Decimal, // the units disposed of which are yet to be matched
+    proceeds: Decimal,
+    matches: Vec<Match>,
+}
+
+/// A capital gains report computed with HMRC's share matching rules. Acquisitions are the lots
+/// added by cost postings, at their cost, and disposals are reductions with a price, at the price.
+/// Disposals are matched, in turn, with:
+/// 1. acquisitions on the same day,
+/// 2. acquisitions in the 30 days after the disposal (the bed and breakfast rule), earliest first,
+/// 3. the Section 104 pool, which holds all other acquisitions at their average cost.
+///
+/// Commodities are pooled across all accounts. A reduction without a price is treated as a
+/// transfer, so it must be matched by acquisitions of the same number of units in the same
+/// transaction.
+#[derive(Debug)]
+pub struct CgtReport {
+    years: BTreeMap<TaxYear, TaxYearReport>,
+    pools: BTreeMap<String, Holding>,
+}
+
+impl CgtReport {
+    /// Computes the capital gains report for `doc`.
+    pub fn new(doc: &AccountsDocument) -> Result<CgtReport, CgtError> {
+        let mut years: BTreeMap<TaxYear, TaxYearReport> = BTreeMap::new();
+        let mut pools = BTreeMap::new();
+
+        for (commodity, days) in collect_days(doc)? {
+            let mut days: Vec<(Date, Day)> = days.into_iter().collect();
+            let pool = match_disposals(&commodity, &mut days)?;
+            pools.insert(commodity.clone(), pool);
+
+            for (date, day) in days {
+                if day.disposed.is_zero() {
+                    continue;
+                }
+
+                let year = TaxYear::containing(date);
+                years
+                    .entry(year)
+                    .or_insert_with(|| TaxYearReport {
+                        year,
+                        disposals: Vec::new(),
+                    })
+                    .disposals
+                    .push(Disposal {
+                        date,
+                        commodity: commodity.clone(),
+                        units: day.disposed,
+                        proceeds: day.proceeds,
+                        matches: day.matches,
+                    });
+            }
+        }
+
+        for report in years.values_mut() {
+            report.disposals.sort_by_key(|d| d.date);
+        }
+
+        Ok(CgtReport { years, pools })
+    }
+
+    /// Returns the reports for the tax years with disposals, in order.
+    pub fn tax_years(&self) -> impl Iterator<Item = &TaxYearReport> {
+        self.years.values()
+    }
+
+    /// Returns the report for `year`, or [`None`] if there were no disposals in the year.
+    pub fn tax_year(&self, year: TaxYear) -> Option<&TaxYearReport> {
+        self.years.get(&year)
+    }
+
+    /// Returns the Section 104 pool of `commodity` after the last transaction.
+    pub fn pool(&self, commodity: &str) -> Option<&Holding> {
+        self.pools.get(commodity)
+    }
+}
+
+/// Collects the acquisitions and disposals in `doc`, by commodity and day.
+fn collect_days(doc: &AccountsDocument) -> Result<BTreeMap<String, BTreeMap<Date, Day>>, CgtError> {
+    let mut commodities: BTreeMap<String, BTreeMap<Date, Day>> = BTreeMap::new();
+
+    for tx in doc.transactions() {
+        let date = tx.date();
+        let mut transferred: BTreeMap<&str, Decimal> = BTreeMap::new();
+        let mut acquired: BTreeMap<&str, Holding> = BTreeMap::new();
+
+        for posting in tx.postings() {
+            let Posting::Cost(posting) = posting else {
+                continue;
+            };
+
+            if posting.units.is_sign_positive() {
+                let holding = acquired.entry(&posting.currency).or_default();
+                for lot in posting.booked() {
+                    let (Some(cost), Some(value)) = (lot.cost(), lot.book_value()) else {
+                        continue;
+                    };
+                    holding.add(lot.units(), to_sterling(doc, value, cost.currency(), date)?);
+                }
+            } else if let Some(price) = &posting.price {
+                let units = -posting.units;
+                let proceeds = to_sterling(doc, units * price.amount, &price.currency, date)?;
+                let day = commodities
+                    .entry(posting.currency.clone())
+                    .or_default()
+                    .entry(date)
+                    .or_default();
+                day.disposed += units;
+                day.unmatched += units;
+                day.proceeds += proceeds;
+            } else {
+                *transferred.entry(&posting.currency).or_default() -= posting.units;
+            }
+        }
+
+        for (commodity, units) in transferred {
+            match acquired.get(commodity) {
+                Some(holding) if holding.units == units => {
+                    acquired.remove(commodity);
+                }
+                _ => {
+                    return Err(CgtError::MissingProceeds {
+                        commodity: commodity.to_string(),
+                        date,
+                    });
+                }
+            }
+        }
+
+        for (commodity, holding) in acquired {
+            commodities
+                .entry(commodity.to_string())
+                .or_default()
+                .entry(date)
+                .or_default()
+                .acquired
+                .add(holding.units, holding.cost);
+        }
+    }
+
+    Ok(commodities)
+}
+
+/// Matches the disposals of `commodity` on `days`, which are in date order, returning the
+/// Section 104 pool after the last day.
+fn match_disposals(commodity: &str, days: &mut [(Date, Day)]) -> Result<Holding, CgtError> {
+    // The same day rule takes precedence over the bed and breakfast rule, so an acquisition is
+    // only matched with an earlier disposal once all same day matches have been made.
+    for (_, day) in days.iter_mut() {
+        let units = day.unmatched.min(day.acquired.units);
+        if units.is_zero() {
+            continue;
+        }
+        let allowable_cost = day.acquired.take(units);
+        day.unmatched -= units;
+        day.matches.push(Match {
+            rule: MatchingRule::SameDay,
+            units,
+            allowable_cost,
+        });
+    }
+
+    for i in 0..days.len() {
+        let last_date = days[i].0 + DateInterval::new(BED_AND_BREAKFAST_DAYS);
+        for j in i + 1..days.len() {
+            let (date, _) = days[j];
+            if date > last_date || days[i].1.unmatched.is_zero() {
+                break;
+            }
+
+            let units = days[i].1.unmatched.min(days[j].1.acquired.units);
+            if units.is_zero() {
+                continue;
+            }
+            let allowable_cost = days[j].1.acquired.take(units);
+            let day = &mut days[i].1;
+            day.unmatched -= units;
+            day.matches.push(Match {
+                rule: MatchingRule::BedAndBreakfast(date),
+                units,
+                allowable_cost,
+            });
+        }
+    }
+
+    let mut pool = Holding::default();
+    for (date, day) in days.iter_mut() {
+        pool.add(day.acquired.units, day.acquired.cost);
+
+        let units = day.unmatched;
+        if units.is_zero() {
+            continue;
+        }
+        if units > pool.units {
+            return Err(CgtError::InsufficientUnits {
+                commodity: commodity.to_string(),
+                date: *date,
+            });
+        }
+        let allowable_cost = pool.take(units);
+        day.unmatched = Decimal::ZERO;
+        day.matches.push(Match {
+            rule: MatchingRule::Section104,
+            units,
+            allowable_cost,
+        });
+    }
+
+    Ok(pool)
+}
+
+/// Converts `amount` of `currency` into sterling using the most recent price on or before `date`.
+fn to_sterling(
+    doc: &AccountsDocument,
+    amount: Decimal,
+    currency: &str,
+    date: Date,
+) -> Result<Decimal, CgtError> {
+    let (_, rate) = doc
+        .prices()
+        .latest_rate(currency, STERLING, date)
+        .ok_or_else(|| CgtError::NoRate {
+            currency: currency.to_string(),
+            date,
+        })?;
+
+    Ok(amount * rate)
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CgtError {
+    #[error("there is no price to convert {currency} into GBP on {date}")]
+    NoRate { currency: String, date: Date },
+    #[error("the reduction of {commodity} on {date} has no price")]
+    MissingProceeds { commodity: String, date: Date },
+    #[error("the disposal of {commodity} on {date} is more than the units held")]
+    InsufficientUnits { commodity: String, date: Date },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_with_recovery, tokenizer::Tokenizer};
+    use date::date;
+    use rust_decimal::dec;
+
+    #[test]
+    fn tax_year_works() {
+        assert_eq!(
+            TaxYear::containing(date! {2024-04-05}),
+            TaxYear::starting_in(2023)
+        );
+        assert_eq!(
+            TaxYear::containing(date! {2024-04-06}),
+            TaxYear::starting_in(2024)
+        );
+        assert_eq!(TaxYear::starting_in(2023).start(), date! {2023-04-06});
+        assert_eq!(TaxYear::starting_in(2023).end(), date! {2024-04-05});
+        assert_eq!(TaxYear::starting_in(2023).to_string(), "2023/24");
+        assert_eq!(TaxYear::starting_in(1999).to_string(), "1999/00");
+    }
+
+    #[test]
+    fn matching_rules_work() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-01-01 open Assets:Broker GBP,VWRL "FIFO"
+2023-01-01 open Assets:Cash GBP
+2023-01-01 open Income:Gains GBP
+
+2023-01-10 * "Buy"
+  Assets:Broker  100 VWRL {10.00 GBP}
+  Assets:Cash
+
+2023-03-01 * "Buy"
+  Assets:Broker  100 VWRL {13.00 GBP}
+  Assets:Cash
+
+2023-03-20 * "Sell from the pool"
+  Assets:Broker  -10 VWRL {} @ 12.00 GBP
+  Assets:Cash  120.00 GBP
+
+2023-05-01 * "Sell"
+  Assets:Broker  -50 VWRL {} @ 15.00 GBP
+  Assets:Cash  750.00 GBP
+
+2023-05-01 * "Buy back on the same day"
+  Assets:Broker  20 VWRL {14.00 GBP}
+  Assets:Cash
+
+2023-05-20 * "Buy back within 30 days"
+  Assets:Broker  10 VWRL {12.00 GBP}
+  Assets:Cash
+
+2023-06-01 * "Buy back after 30 days"
+  Assets:Broker  30 VWRL {11.00 GBP}
+  Assets:Cash
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+        assert_eq!(errors, vec![]);
+
+        let report = CgtReport::new(&accts).expect("the report is computed");
+        let years = report.tax_years().collect::<Vec<_>>();
+        assert_eq!(years.len(), 2);
+
+        assert_eq!(years[0].year(), TaxYear::starting_in(2022));
+        assert_eq!(years[0].disposals().len(), 1);
+        let disposal = &years[0].disposals()[0];
+        assert_eq!(disposal.matches().len(), 1);
+        assert_eq!(disposal.matches()[0].rule(), MatchingRule::Section104);
+        assert_eq!(disposal.allowable_cost(), dec!(115));
+        assert_eq!(disposal.gain(), dec!(5));
+
+        assert_eq!(years[1].year(), TaxYear::starting_in(2023));
+        let disposal = &years[1].disposals()[0];
+        assert_eq!(
+            disposal
+                .matches()
+                .iter()
+                .map(|m| (m.rule(), m.units(), m.allowable_cost()))
+                .collect::<Vec<_>>(),
+            vec![
+                (MatchingRule::SameDay, dec!(20), dec!(280)),
+                (
+                    MatchingRule::BedAndBreakfast(date! {2023-05-20}),
+                    dec!(10),
+                    dec!(120)
+                ),
+                (MatchingRule::Section104, dec!(20), dec!(230)),
+            ]
+        );
+        assert_eq!(disposal.proceeds(), dec!(750));
+        assert_eq!(years[1].gains(), dec!(120));
+        assert_eq!(years[1].losses(), dec!(0));
+
+        let pool = report.pool("VWRL").expect("VWRL is held");
+        assert_eq!(pool.units(), dec!(200));
+        assert_eq!(pool.cost(), dec!(2285));
+    }
+}
@@ -0,0 +1,413 @@
+use std::{fmt::Display, ops::Range};
+
+use crate::tokenizer::{Token, TokenKind, TokenizeError, Tokenizer, Trivia, TriviaKind};
+
+/// The kind of [`SyntaxNode`], found from the tokens it starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Option,
+    Open,
+    Balance,
+    Close,
+    Pad,
+    Price,
+    Include,
+    Transaction,
+    /// Lines which don't start a directive e.g. postings after a blank line, or a date followed
+    /// by something other than a directive.
+    Invalid,
+}
+
+/// A token along with the trivia attached to it. The trivia after a token on the same line is its
+/// trailing trivia. The rest of the trivia, such as indentation, blank lines and lines with only a
+/// comment, is the leading trivia of the token after it.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxToken {
+    leading_trivia: Vec<Trivia>,
+    token: Result<Token, TokenizeError>,
+    trailing_trivia: Vec<Trivia>,
+}
+
+impl SyntaxToken {
+    /// Returns the token, or the error for a sequence of characters which couldn't be tokenized.
+    pub fn token(&self) -> Result<&Token, &TokenizeError> {
+        self.token.as_ref()
+    }
+
+    /// Returns the trivia before the token.
+    pub fn leading_trivia(&self) -> &[Trivia] {
+        &self.leading_trivia
+    }
+
+    /// Returns the trivia after the token, up to the next token on the same line.
+    pub fn trailing_trivia(&self) -> &[Trivia] {
+        &self.trailing_trivia
+    }
+
+    /// Returns the byte range of the token in the source, without its trivia.
+    pub fn span(&self) -> Range<usize> {
+        match &self.token {
+            Ok(token) => token.span.clone(),
+            Err(error) => error.span(),
+        }
+    }
+
+    /// Returns the byte range of the token in the source, along with its trivia.
+    pub fn full_span(&self) -> Range<usize> {
+        let span = self.span();
+        let start = self
+            .leading_trivia
+            .first()
+            .map_or(span.start, |t| t.span.start);
+        let end = self.trailing_trivia.last().map_or(span.end, |t| t.span.end);
+        start..end
+    }
+}
+
+/// A directive, made of the tokens from the start of its first line up to and including the
+/// newline at the end of its last line. A transaction goes on until a blank line or the start of
+/// another directive, every other directive is a single line.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxNode {
+    kind: NodeKind,
+    tokens: Vec<SyntaxToken>,
+}
+
+impl SyntaxNode {
+    /// Returns the kind of directive.
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /// Returns the tokens of the directive, of which there's at least one.
+    pub fn tokens(&self) -> &[SyntaxToken] {
+        &self.tokens
+    }
+
+    /// Returns the byte range of the directive in the source, along with the trivia of its tokens.
+    pub fn full_span(&self) -> Range<usize> {
+        let first = self.tokens.first().expect("a node has at least one token");
+        let last = self.tokens.last().expect("a node has at least one token");
+        first.full_span().start..last.full_span().end
+    }
+}
+
+/// A lossless concrete syntax tree of a source, in which every byte of the source is part of a
+/// token or of the trivia attached to one. Unlike [`parse`](crate::parser::parse) it keeps the
+/// comments, blank lines and spacing, so it's what formatters and tools which edit the source are
+/// built on. Displaying the tree writes out the text of its tokens and trivia, which gives back the
+/// source it was parsed from.
+///
+/// # Examples
+///
+/// ```
+/// use recount::cst::{NodeKind, SyntaxTree};
+///
+/// let source = "option \"title\" \"Accounts\"\n\n; Coffee\n2023-02-01 open Assets:Cash ; wallet\n";
+/// let tree = SyntaxTree::parse(source);
+///
+/// let kinds: Vec<_> = tree.nodes().iter().map(|node| node.kind()).collect();
+/// assert_eq!(kinds, vec![NodeKind::Option, NodeKind::Open]);
+/// assert_eq!(tree.to_string(), source);
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct SyntaxTree {
+    source: String,
+    nodes: Vec<SyntaxNode>,
+    trailing_trivia: Vec<Trivia>,
+}
+
+impl SyntaxTree {
+    /// Parses `source` into a tree. Sequences of characters which can't be tokenized are kept as
+    /// tokens holding the error, see [`SyntaxToken::token`], so this never fails.
+    pub fn parse(source: &str) -> Self {
+        let mut tokenizer = Tokenizer::new(source).keep_trivia();
+        // The tokens of each node, whose kinds are found once they're complete
+        let mut nodes: Vec<Vec<SyntaxToken>> = vec![];
+        // The trivia which will be the leading trivia of the next token
+        let mut leading_trivia = vec![];
+        // Whether a token or an error has been found on the current line
+        let mut line_has_tokens = false;
+        // Whether the last node can go on to the next line, which is only the case until a blank
+        // line
+        let mut node_open = false;
+
+        while let Some(token) = tokenizer.next() {
+            let span = match &token {
+                Ok(token) => token.span.clone(),
+                Err(error) => error.span(),
+            };
+            // The trivia after the token is the rest of the line skipped after an error
+            let (before, after): (Vec<_>, Vec<_>) = tokenizer
+                .take_trivia()
+                .into_iter()
+                .partition(|trivia| trivia.span.start < span.start);
+
+            let previous = nodes.last_mut().and_then(|tokens| tokens.last_mut());
+            match previous {
+                Some(previous) if line_has_tokens => previous.trailing_trivia.extend(before),
+                _ => leading_trivia.extend(before),
+            }
+
+            let is_newline = matches!(
+                token,
+                Ok(Token {
+                    kind: TokenKind::Newline,
+                    ..
+                })
+            );
+            if is_newline && !line_has_tokens {
+                leading_trivia.push(Trivia {
+                    kind: TriviaKind::Newline,
+                    span,
+                });
+                node_open = false;
+                continue;
+            }
+
+            // Lines which don't start a directive carry on a transaction, or invalid lines
+            if !line_has_tokens {
+                let carries_on = node_open
+                    && !starts_directive(&token)
+                    && nodes.last().is_some_and(|tokens| {
+                        matches!(node_kind(tokens), NodeKind::Transaction | NodeKind::Invalid)
+                    });
+                if !carries_on {
+                    nodes.push(vec![]);
+                }
+                node_open = true;
+            }
+
+            line_has_tokens = !is_newline;
+            nodes
+                .last_mut()
+                .expect("a node was started at the start of the line")
+                .push(SyntaxToken {
+                    leading_trivia: std::mem::take(&mut leading_trivia),
+                    token,
+                    trailing_trivia: after,
+                });
+        }
+
+        let before_end = tokenizer.take_trivia();
+        match nodes.last_mut().and_then(|tokens| tokens.last_mut()) {
+            Some(last) if line_has_tokens => last.trailing_trivia.extend(before_end),
+            _ => leading_trivia.extend(before_end),
+        }
+
+        SyntaxTree {
+            source: source.to_string(),
+            nodes: nodes
+                .into_iter()
+                .map(|tokens| SyntaxNode {
+                    kind: node_kind(&tokens),
+                    tokens,
+                })
+                .collect(),
+            trailing_trivia: leading_trivia,
+        }
+    }
+
+    /// Returns the source the tree was parsed from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the directives in the order of the source.
+    pub fn nodes(&self) -> &[SyntaxNode] {
+        &self.nodes
+    }
+
+    /// Returns the trivia after the last token, such as blank lines and comments at the end of
+    /// the source.
+    pub fn trailing_trivia(&self) -> &[Trivia] {
+        &self.trailing_trivia
+    }
+
+    /// Returns the text of the source in `span`, such as the span of a token or of trivia.
+    pub fn text(&self, span: Range<usize>) -> &str {
+        &self.source[span]
+    }
+}
+
+/// Writes the text of every token and trivia in the tree, in order.
+impl Display for SyntaxTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for token in self.nodes.iter().flat_map(|node| &node.tokens) {
+            for trivia in &token.leading_trivia {
+                f.write_str(self.text(trivia.span.clone()))?;
+            }
+            f.write_str(self.text(token.span()))?;
+            for trivia in &token.trailing_trivia {
+                f.write_str(self.text(trivia.span.clone()))?;
+            }
+        }
+        for trivia in &self.trailing_trivia {
+            f.write_str(self.text(trivia.span.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if a line starting with `token` is the start of a directive.
+fn starts_directive(token: &Result<Token, TokenizeError>) -> bool {
+    matches!(
+        token,
+        Ok(Token {
+            kind: TokenKind::Date(_) | TokenKind::OptionLine { .. } | TokenKind::DirectiveInclude,
+            ..
+        })
+    )
+}
+
+/// Returns the kind of node made of `tokens`, which is decided by its first two tokens.
+fn node_kind(tokens: &[SyntaxToken]) -> NodeKind {
+    let mut kinds = tokens
+        .iter()
+        .map(|token| token.token().ok().map(|token| &token.kind));
+
+    match (kinds.next().flatten(), kinds.next().flatten()) {
+        (Some(TokenKind::OptionLine { .. }), _) => NodeKind::Option,
+        (Some(TokenKind::DirectiveInclude), _) => NodeKind::Include,
+        (Some(TokenKind::Date(_)), Some(kind)) => match kind {
+            TokenKind::DirectiveOpen => NodeKind::Open,
+            TokenKind::DirectiveBalance => NodeKind::Balance,
+            TokenKind::DirectiveClose => NodeKind::Close,
+            TokenKind::DirectivePad => NodeKind::Pad,
+            TokenKind::DirectivePrice => NodeKind::Price,
+            TokenKind::DirectivePostTx => NodeKind::Transaction,
+            _ => NodeKind::Invalid,
+        },
+        _ => NodeKind::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tests::{SOURCES, generated_sources};
+
+    /// The kinds of a token's leading trivia, its text, and the kinds of its trailing trivia.
+    type TokenDescription<'a> = (Vec<TriviaKind>, &'a str, Vec<TriviaKind>);
+
+    /// Returns the kind of each node in `tree`, along with a description of each of its tokens.
+    fn describe(tree: &SyntaxTree) -> Vec<(NodeKind, Vec<TokenDescription<'_>>)> {
+        let kinds = |trivia: &[Trivia]| trivia.iter().map(|t| t.kind).collect();
+        tree.nodes()
+            .iter()
+            .map(|node| {
+                let tokens = node
+                    .tokens()
+                    .iter()
+                    .map(|token| {
+                        (
+                            kinds(token.leading_trivia()),
+                            tree.text(token.span()),
+                            kinds(token.trailing_trivia()),
+                        )
+                    })
+                    .collect();
+                (node.kind(), tokens)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_works() {
+        use TriviaKind::*;
+
+        let source = "option \"title\" \"Café\" ; Nerö\n\n\
+            ; Coffee\n\
+            2023-02-01 * \"Café Nerö\"  \n\
+            \x20 Expenses:Coffee  3.50 GBP\n\
+            \x20 Assets:Cash\n\
+            \n\
+            \x20 Assets:Bank\n\
+            2023-02-02 open Assets:Café $$$ GBP\n\
+            ; end";
+        let tree = SyntaxTree::parse(source);
+
+        assert_eq!(
+            describe(&tree),
+            vec![
+                (
+                    NodeKind::Option,
+                    vec![
+                        (
+                            vec![],
+                            "option \"title\" \"Café\"",
+                            vec![Whitespace, Comment]
+                        ),
+                        (vec![], "\n", vec![]),
+                    ]
+                ),
+                (
+                    NodeKind::Transaction,
+                    vec![
+                        (
+                            vec![Newline, Comment, Newline],
+                            "2023-02-01",
+                            vec![Whitespace]
+                        ),
+                        (vec![], "*", vec![Whitespace]),
+                        (vec![], "\"Café Nerö\"", vec![Whitespace]),
+                        (vec![], "\n", vec![]),
+                        (vec![Whitespace], "Expenses:Coffee", vec![Whitespace]),
+                        (vec![], "3.50 GBP", vec![]),
+                        (vec![], "\n", vec![]),
+                        (vec![Whitespace], "Assets:Cash", vec![]),
+                        (vec![], "\n", vec![]),
+                    ]
+                ),
+                (
+                    NodeKind::Invalid,
+                    vec![
+                        (vec![Newline, Whitespace], "Assets:Bank", vec![]),
+                        (vec![], "\n", vec![]),
+                    ]
+                ),
+                (
+                    NodeKind::Open,
+                    vec![
+                        (vec![], "2023-02-02", vec![Whitespace]),
+                        (vec![], "open", vec![Whitespace]),
+                        (vec![], "Assets:Café", vec![Skipped]),
+                        (vec![], "\n", vec![]),
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(tree.trailing_trivia()[0].kind, Comment);
+        assert_eq!(tree.nodes()[3].tokens()[2].full_span(), 143..163);
+        assert!(tree.nodes()[3].tokens()[2].token().is_err());
+        assert_eq!(tree.to_string(), source);
+    }
+
+    #[test]
+    fn round_trips() {
+        for source in SOURCES
+            .iter()
+            .copied()
+            .map(str::to_string)
+            .chain(generated_sources(5_000))
+        {
+            let tree = SyntaxTree::parse(&source);
+            assert_eq!(tree.to_string(), source);
+
+            // Every token and trivia follows on from the one before
+            let mut end = 0;
+            for token in tree.nodes().iter().flat_map(|node| node.tokens()) {
+                for span in token
+                    .leading_trivia()
+                    .iter()
+                    .map(|t| t.span.clone())
+                    .chain([token.span()])
+                    .chain(token.trailing_trivia().iter().map(|t| t.span.clone()))
+                {
+                    assert_eq!(span.start, end, "{source:?}");
+                    end = span.end;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::{fmt::Display, ops::Range};
+
+use crate::{
+    accounts_doc::{AddTransactionError, CloseAccountError},
+    options::OptionError,
+    parser::{ParseError, ParseErrorKind},
+    tokenizer::TokenizeError,
+};
+
+/// How serious a [`Diagnostic`] is. Errors stop the document from being used, warnings don't.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// A message about a position in a source file, which can be rendered along with the offending
+/// source line. The rendered diagnostic takes the form:
+/// ```text
+/// error: expected currency, found newline
+///  --> ledger.beancount:3:37
+///   |
+/// 3 | 2023-02-01 open Expenses:Coffee GBP,
+///   |                                     ^
+///   = help: an optional note
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    line: usize,
+    column: usize,
+    span: Range<usize>,
+    help: Option<String>,
+}
+
+impl Diagnostic {
+    /// Diagnostic constructor. The `line` and `column` are one-indexed, the `span` is the byte
+    /// range of the source to underline.
+    pub fn new(message: impl Into<String>, line: usize, column: usize, span: Range<usize>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            line,
+            column,
+            span,
+            help: None,
+        }
+    }
+
+    /// Sets the severity, which is [`Severity::Error`] unless it's set.
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /// Returns the severity of the diagnostic.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Adds a "help:" note which is rendered below the source snippet.
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Replaces the span, which is used when the span was relative to something other than the
+    /// source the diagnostic is rendered with.
+    pub(crate) fn with_span(mut self, span: Range<usize>) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Renders the diagnostic. The `source` must be the contents of the file named `file_name`,
+    /// since the source line is found using the span. If the span isn't in `source` then the
+    /// snippet is left out.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let line_number = self.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let mut rendered = format!(
+            "{}: {}\n{gutter}--> {file_name}:{}:{}\n",
+            self.severity, self.message, self.line, self.column
+        );
+
+        if let Some((text, underline)) = self.snippet(source) {
+            rendered.push_str(&format!("{gutter} |\n"));
+            rendered.push_str(&format!("{line_number} | {text}\n"));
+            rendered.push_str(&format!("{gutter} | {underline}\n"));
+        }
+
+        if let Some(help) = &self.help {
+            rendered.push_str(&format!("{gutter} = help: {help}\n"));
+        }
+
+        rendered
+    }
+
+    /// Returns the source line containing the start of the span, along with a line of carets
+    /// which underline the span. Returns [`None`] if the span isn't in `source`.
+    fn snippet<'a>(&self, source: &'a str) -> Option<(&'a str, String)> {
+        let start = self.span.start;
+        let before = source.get(..start)?;
+
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Keep any tabs so that the carets line up with the text above them
+        let mut underline: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        // Only the part of the span which is on the first line is underlined. Spans which are
+        // empty, or only cover the newline, still get a single caret.
+        let end = self.span.end.clamp(start, line_start + text.len());
+        let width = source.get(start..end).map_or(0, |s| s.chars().count());
+        underline.push_str(&"^".repeat(width.max(1)));
+
+        Some((text, underline))
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let severity = if error.is_warning() {
+            Severity::Warning
+        } else {
+            Severity::Error
+        };
+        let diagnostic = Diagnostic::new(
+            error.to_string(),
+            error.line(),
+            error.column(),
+            error.span(),
+        )
+        .with_severity(severity);
+
+        match help(error.kind()) {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&TokenizeError> for Diagnostic {
+    fn from(error: &TokenizeError) -> Self {
+        Diagnostic::new(error.message(), error.line(), error.column(), error.span())
+    }
+}
+
+/// Returns a note explaining how to fix the error, for errors where the message alone might not be
+/// enough.
+fn help(kind: &ParseErrorKind) -> Option<&'static str> {
+    match kind {
+        ParseErrorKind::DuplicateAccount => Some("an account can only be opened once"),
+        ParseErrorKind::TransactionInvalid(AddTransactionError::AccountNotFound) => {
+            Some("accounts must be opened with an open directive before they're posted to")
+        }
+        ParseErrorKind::TransactionInvalid(AddTransactionError::AccountNotOpen) => {
+            Some("the transaction is dated before the account's open directive")
+        }
+        ParseErrorKind::TransactionInvalid(AddTransactionError::AccountClosed) => {
+            Some("the transaction is dated after the account's close directive")
+        }
+        ParseErrorKind::TransactionInvalid(AddTransactionError::NotBalanced { .. }) => Some(
+            "the postings must sum to zero, or one posting can leave out its amount to balance the transaction",
+        ),
+        ParseErrorKind::TransactionInvalid(AddTransactionError::GainsAccountNotOpen) => {
+            Some("open the gains account, `Income:Gains` by default, or add a posting for the gain")
+        }
+        ParseErrorKind::OptionInvalid(OptionError::Unknown(_)) => {
+            Some("the option is ignored, check the spelling of its name")
+        }
+        ParseErrorKind::CloseInvalid(CloseAccountError::NonZeroBalance(_)) => Some(
+            "the account is still closed, move its balance to another account before the close",
+        ),
+        ParseErrorKind::UnusedPad => {
+            Some("a pad must be followed by a balance assertion which doesn't hold without padding")
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parser::{parse, parse_with_recovery},
+        tokenizer::Tokenizer,
+    };
+
+    #[test]
+    fn render_works() {
+        let source = "option \"title\" \"x\"\n2023-02-01 open Expenses:Coffee GBP,\n";
+        let error = parse(Tokenizer::new(source)).unwrap_err();
+
+        assert_eq!(
+            Diagnostic::from(&error).render("ledger.beancount", source),
+            "error: expected currency, found newline
+ --> ledger.beancount:2:37
+  |
+2 | 2023-02-01 open Expenses:Coffee GBP,
+  |                                     ^
+"
+        );
+    }
+
+    #[test]
+    fn render_warning() {
+        let source = "option \"title\" \"x\"\noption \"tilte\" \"y\"\n";
+        let (_, errors) = parse_with_recovery(Tokenizer::new(source));
+
+        assert_eq!(
+            Diagnostic::from(&errors[0]).render("ledger.beancount", source),
+            "warning: unknown option \"tilte\"
+ --> ledger.beancount:2:1
+  |
+2 | option \"tilte\" \"y\"
+  | ^^^^^^^^^^^^^^^^^^
+  = help: the option is ignored, check the spelling of its name
+"
+        );
+    }
+
+    #[test]
+    fn render_underlines_span_and_help() {
+        let source = "2023-02-01 open\n\t  Assets:Café  $$$ GBP\r\n";
+
+        assert_eq!(
+            Diagnostic::new("unexpected character sequence", 2, 18, 33..36)
+                .with_help("remove it")
+                .render("a.beancount", source),
+            "error: unexpected character sequence
+ --> a.beancount:2:18
+  |
+2 | \t  Assets:Café  $$$ GBP
+  | \t               ^^^
+  = help: remove it
+"
+        );
+    }
+
+    #[test]
+    fn render_without_source() {
+        assert_eq!(
+            Diagnostic::new("expected option line", 0, 0, 10..10).render("a.beancount", ""),
+            "error: expected option line
+ --> a.beancount:0:0
+"
+        );
+    }
+}
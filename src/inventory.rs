@@ -0,0 +1,547 @@
+use std::{cmp::Reverse, collections::BTreeMap, fmt::Display};
+
+use date::Date;
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{
+    prices::PriceDb,
+    types::{Amount, BookingMethod},
+};
+
+/// The cost of one unit of a lot, along with the date the lot was acquired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cost {
+    pub(crate) number: Decimal,
+    pub(crate) currency: String,
+    pub(crate) date: Date,
+}
+
+impl Cost {
+    /// Returns the cost of one unit.
+    pub fn number(&self) -> Decimal {
+        self.number
+    }
+
+    /// Returns the currency of the cost.
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Returns the date the lot was acquired.
+    pub fn date(&self) -> Date {
+        self.date
+    }
+}
+
+/// The cost specification of a posting, which takes one of the forms:
+/// ```beancount
+/// Assets:Broker  10 VWRL {85.20 GBP}
+/// Assets:Broker  10 VWRL {{852 GBP}}
+/// Assets:Broker  10 VWRL {85.20 GBP, 2023-02-01}
+/// Assets:Broker  -5 VWRL {}
+/// ```
+/// The first is a per unit cost and the second is the total cost of all the units. Any part can
+/// be left out of a reduction, in which case it matches any lot.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostSpec {
+    pub(crate) per_unit: Option<Decimal>,
+    pub(crate) total: Option<Decimal>,
+    pub(crate) currency: Option<String>,
+    pub(crate) date: Option<Date>,
+}
+
+impl CostSpec {
+    /// Returns the cost of one unit, for a posting of `units`, if the specification has a number.
+    /// A total cost can't be divided between zero units.
+    fn number(&self, units: Decimal) -> Result<Option<Decimal>, BookingError> {
+        match (self.per_unit, self.total) {
+            (Some(per_unit), _) => Ok(Some(per_unit)),
+            (None, Some(_)) if units.is_zero() => Err(BookingError::TotalCostOnZeroUnits),
+            (None, Some(total)) => Ok(Some(total / units.abs())),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Returns `true` if a lot with `cost` is matched by the specification, whose cost of one unit
+    /// is `number`.
+    fn matches(&self, cost: &Cost, number: Option<Decimal>) -> bool {
+        number.is_none_or(|n| n == cost.number)
+            && self.currency.as_ref().is_none_or(|c| c == &cost.currency)
+            && self.date.is_none_or(|d| d == cost.date)
+    }
+}
+
+/// An amount of a commodity held in an [`Inventory`], which is either held at cost (a lot) or
+/// not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub(crate) units: Decimal,
+    pub(crate) currency: String,
+    pub(crate) cost: Option<Cost>,
+}
+
+impl Position {
+    /// Returns the number of units held.
+    pub fn units(&self) -> Decimal {
+        self.units
+    }
+
+    /// Returns the commodity held.
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Returns the cost of each unit if the position is a lot.
+    pub fn cost(&self) -> Option<&Cost> {
+        self.cost.as_ref()
+    }
+
+    /// Returns the number of units multiplied by the cost of each unit, or [`None`] if the
+    /// position isn't held at cost.
+    pub(crate) fn book_value(&self) -> Option<Decimal> {
+        self.cost.as_ref().map(|cost| self.units * cost.number)
+    }
+
+    /// Creates a lot for a posting of `units` of `currency` with the cost specification `spec`.
+    /// Lots without a date are dated `date`. The cost must have a number and a currency.
+    pub(crate) fn lot(
+        units: Decimal,
+        currency: &str,
+        spec: &CostSpec,
+        date: Date,
+    ) -> Result<Position, BookingError> {
+        let (Some(number), Some(cost_currency)) = (spec.number(units)?, &spec.currency) else {
+            return Err(BookingError::IncompleteCost);
+        };
+
+        Ok(Position {
+            units,
+            currency: currency.to_string(),
+            cost: Some(Cost {
+                number,
+                currency: cost_currency.clone(),
+                date: spec.date.unwrap_or(date),
+            }),
+        })
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.units, self.currency)?;
+        if let Some(cost) = &self.cost {
+            write!(f, " {{{} {}, {}}}", cost.number, cost.currency, cost.date)?;
+        }
+        Ok(())
+    }
+}
+
+/// The holdings of an account, made up of [`Position`]s. Units of a commodity which aren't held
+/// at cost are merged into a single position, as are lots with the same cost. Positions with zero
+/// units are removed, so the inventory of an account which has never been posted to is empty.
+/// Positions are kept in commodity order, and lots of the same commodity in the order they were
+/// added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory {
+    positions: Vec<Position>,
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory::default()
+    }
+
+    /// Adds `amount` of `currency`, which isn't held at cost, to the inventory.
+    pub fn add(&mut self, currency: &str, amount: Decimal) {
+        self.add_position(Position {
+            units: amount,
+            currency: currency.to_string(),
+            cost: None,
+        });
+    }
+
+    /// Adds `position` to the inventory, merging it with any position of the same commodity and
+    /// cost.
+    pub fn add_position(&mut self, position: Position) {
+        if let Some(index) = self
+            .positions
+            .iter()
+            .position(|p| p.currency == position.currency && p.cost == position.cost)
+        {
+            self.positions[index].units += position.units;
+            if self.positions[index].units.is_zero() {
+                self.positions.remove(index);
+            }
+        } else if !position.units.is_zero() {
+            let index = self
+                .positions
+                .partition_point(|p| p.currency <= position.currency);
+            self.positions.insert(index, position);
+        }
+    }
+
+    /// Adds every position in `other` to the inventory.
+    pub fn add_inventory(&mut self, other: &Inventory) {
+        for position in &other.positions {
+            self.add_position(position.clone());
+        }
+    }
+
+    /// Returns the amount of `currency` held, including lots, which is zero if the inventory
+    /// doesn't hold `currency`.
+    pub fn get(&self, currency: &str) -> Decimal {
+        self.positions
+            .iter()
+            .filter(|p| p.currency == currency)
+            .map(|p| p.units)
+            .sum()
+    }
+
+    /// Returns `true` if the inventory holds nothing.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Returns the positions held.
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    /// Returns an iterator over the total amount of each commodity held, in commodity order.
+    pub fn amounts(&self) -> impl Iterator<Item = Amount> + '_ {
+        let mut amounts = BTreeMap::<&str, Decimal>::new();
+        for position in &self.positions {
+            *amounts.entry(&position.currency).or_default() += position.units;
+        }
+
+        amounts
+            .into_iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|(currency, amount)| Amount {
+                currency: currency.to_string(),
+                amount,
+            })
+    }
+
+    /// Converts everything held into `currency` using the most recent rates in `prices` declared
+    /// on or before `date`, see [`PriceDb::latest_rate`]. Lots are valued by their units, not
+    /// their cost. Returns a [`ConversionError`] listing every commodity held which has no rate.
+    pub fn convert(
+        &self,
+        prices: &PriceDb,
+        currency: &str,
+        date: Date,
+    ) -> Result<Amount, ConversionError> {
+        let mut total = Decimal::ZERO;
+        let mut missing = vec![];
+        for amount in self.amounts() {
+            match prices.convert(&amount, currency, date) {
+                Some(converted) => total += converted.amount,
+                None => missing.push(amount.currency),
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(ConversionError::NoRate {
+                currencies: missing,
+                currency: currency.to_string(),
+            });
+        }
+        Ok(Amount {
+            currency: currency.to_string(),
+            amount: total,
+        })
+    }
+
+    /// Books a reduction of `units` (which are negative) of `currency` against the lots in the
+    /// inventory, using `method` to choose between the lots matched by `spec`. Returns the
+    /// positions to add to the inventory to make the reduction. Reductions booked with
+    /// [`BookingMethod::None`] aren't matched, they're returned as a lot with the cost in `spec`.
+    pub(crate) fn book_reduction(
+        &self,
+        units: Decimal,
+        currency: &str,
+        spec: &CostSpec,
+        method: BookingMethod,
+        date: Date,
+    ) -> Result<Vec<Position>, BookingError> {
+        if method == BookingMethod::None {
+            return Ok(vec![Position::lot(units, currency, spec, date)?]);
+        }
+
+        let number = spec.number(units)?;
+        let mut matches: Vec<&Position> = self
+            .positions
+            .iter()
+            .filter(|p| {
+                p.currency == currency
+                    && p.units.is_sign_positive()
+                    && p.cost.as_ref().is_some_and(|c| spec.matches(c, number))
+            })
+            .collect();
+
+        if matches.is_empty() {
+            return Err(BookingError::NoMatchingLot);
+        }
+
+        let wanted = -units;
+        let available: Decimal = matches.iter().map(|p| p.units).sum();
+        if wanted > available {
+            return Err(BookingError::InsufficientUnits);
+        }
+
+        match method {
+            BookingMethod::Strict if matches.len() > 1 && wanted != available => {
+                return Err(BookingError::AmbiguousLot);
+            }
+            BookingMethod::Fifo => matches.sort_by_key(|p| p.cost.as_ref().map(|c| c.date)),
+            BookingMethod::Lifo => {
+                // Lots acquired on the same day are also reduced newest first
+                matches.reverse();
+                matches.sort_by_key(|p| Reverse(p.cost.as_ref().map(|c| c.date)));
+            }
+            BookingMethod::Average => return average(&matches, wanted, available),
+            _ => {}
+        }
+
+        let mut remaining = wanted;
+        let mut booked = vec![];
+        for lot in matches {
+            if remaining.is_zero() {
+                break;
+            }
+            let reduction = remaining.min(lot.units);
+            booked.push(Position {
+                units: -reduction,
+                currency: lot.currency.clone(),
+                cost: lot.cost.clone(),
+            });
+            remaining -= reduction;
+        }
+
+        Ok(booked)
+    }
+}
+
+/// Books a reduction of `wanted` units against the average cost of `lots`, which hold `available`
+/// units. The lots are removed and replaced by a single lot, dated the earliest of their dates, of
+/// the units which remain after the reduction.
+fn average(
+    lots: &[&Position],
+    wanted: Decimal,
+    available: Decimal,
+) -> Result<Vec<Position>, BookingError> {
+    let costs: Vec<&Cost> = lots.iter().filter_map(|p| p.cost.as_ref()).collect();
+    if costs.iter().any(|c| c.currency != costs[0].currency) {
+        return Err(BookingError::AmbiguousLot);
+    }
+
+    let total: Decimal = lots.iter().filter_map(|p| p.book_value()).sum();
+    let date = costs
+        .iter()
+        .map(|c| c.date)
+        .min()
+        .expect("there is at least one lot");
+
+    let mut booked: Vec<Position> = lots
+        .iter()
+        .map(|p| Position {
+            units: -p.units,
+            currency: p.currency.clone(),
+            cost: p.cost.clone(),
+        })
+        .collect();
+    booked.push(Position {
+        units: available - wanted,
+        currency: lots[0].currency.clone(),
+        cost: Some(Cost {
+            number: total / available,
+            currency: costs[0].currency.clone(),
+            date,
+        }),
+    });
+
+    Ok(booked)
+}
+
+impl FromIterator<Amount> for Inventory {
+    fn from_iter<T: IntoIterator<Item = Amount>>(iter: T) -> Self {
+        let mut inventory = Inventory::new();
+        for amount in iter {
+            inventory.add(&amount.currency, amount.amount);
+        }
+        inventory
+    }
+}
+
+impl Display for Inventory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("nothing");
+        }
+
+        for (index, position) in self.positions.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{position}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The error returned when a posting with a cost can't be booked.
+#[derive(Error, Debug, PartialEq)]
+pub enum BookingError {
+    #[error("the cost of a new lot must have a number and a currency")]
+    IncompleteCost,
+    #[error("a total cost needs a non-zero number of units")]
+    TotalCostOnZeroUnits,
+    #[error("no lot matches the cost")]
+    NoMatchingLot,
+    #[error("more than one lot matches the cost")]
+    AmbiguousLot,
+    #[error("the matching lots don't hold enough units")]
+    InsufficientUnits,
+}
+
+/// The error returned by [`Inventory::convert`].
+#[derive(Error, Debug, PartialEq)]
+pub enum ConversionError {
+    /// Holds the commodities, in commodity order, which have no rate into `currency`.
+    #[error("no rate for converting {} into {currency}", currencies.join(", "))]
+    NoRate {
+        currencies: Vec<String>,
+        currency: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use date::date;
+    use rust_decimal::dec;
+
+    #[test]
+    fn add_works() {
+        let mut inventory = Inventory::new();
+        inventory.add("VWRL", 10.into());
+        inventory.add("GBP", dec!(-852.00));
+        inventory.add("GBP", dec!(52));
+
+        assert_eq!(inventory.get("GBP"), dec!(-800.00));
+        assert_eq!(inventory.get("USD"), Decimal::ZERO);
+        assert_eq!(inventory.to_string(), "-800.00 GBP, 10 VWRL");
+
+        inventory.add("VWRL", (-10).into());
+        assert_eq!(
+            inventory.amounts().collect::<Vec<_>>(),
+            vec![Amount::new(dec!(-800.00), "GBP")]
+        );
+        assert_eq!(
+            inventory
+                .amounts()
+                .map(|amount| amount.to_string())
+                .collect::<Vec<_>>(),
+            vec!["-800.00 GBP"]
+        );
+
+        inventory.add("GBP", dec!(800));
+        assert!(inventory.is_empty());
+        assert_eq!(inventory.to_string(), "nothing");
+    }
+
+    fn lot(units: i64, number: Decimal, date: Date) -> Position {
+        Position {
+            units: units.into(),
+            currency: "VWRL".to_string(),
+            cost: Some(Cost {
+                number,
+                currency: "GBP".to_string(),
+                date,
+            }),
+        }
+    }
+
+    #[test]
+    fn book_reduction_works() {
+        let mut inventory = Inventory::new();
+        inventory.add_position(lot(10, dec!(85.20), date! {2023-02-01}));
+        inventory.add_position(lot(10, dec!(90.00), date! {2023-01-01}));
+        assert_eq!(
+            inventory.to_string(),
+            "10 VWRL {85.20 GBP, 2023-02-01}, 10 VWRL {90.00 GBP, 2023-01-01}"
+        );
+
+        let any = CostSpec::default();
+        let book = |units: i64, spec: &CostSpec, method| {
+            inventory.book_reduction(units.into(), "VWRL", spec, method, date! {2023-03-01})
+        };
+
+        assert_eq!(
+            book(-5, &any, BookingMethod::Strict),
+            Err(BookingError::AmbiguousLot)
+        );
+        assert_eq!(
+            book(-20, &any, BookingMethod::Strict),
+            Ok(vec![
+                lot(-10, dec!(85.20), date! {2023-02-01}),
+                lot(-10, dec!(90.00), date! {2023-01-01}),
+            ])
+        );
+        assert_eq!(
+            book(
+                -5,
+                &CostSpec {
+                    per_unit: Some(dec!(85.2)),
+                    ..CostSpec::default()
+                },
+                BookingMethod::Strict
+            ),
+            Ok(vec![lot(-5, dec!(85.20), date! {2023-02-01})])
+        );
+        assert_eq!(
+            book(
+                -5,
+                &CostSpec {
+                    date: Some(date! {2023-01-02}),
+                    ..CostSpec::default()
+                },
+                BookingMethod::Strict
+            ),
+            Err(BookingError::NoMatchingLot)
+        );
+        assert_eq!(
+            book(-21, &any, BookingMethod::Fifo),
+            Err(BookingError::InsufficientUnits)
+        );
+        assert_eq!(
+            book(-15, &any, BookingMethod::Fifo),
+            Ok(vec![
+                lot(-10, dec!(90.00), date! {2023-01-01}),
+                lot(-5, dec!(85.20), date! {2023-02-01}),
+            ])
+        );
+        assert_eq!(
+            book(-15, &any, BookingMethod::Lifo),
+            Ok(vec![
+                lot(-10, dec!(85.20), date! {2023-02-01}),
+                lot(-5, dec!(90.00), date! {2023-01-01}),
+            ])
+        );
+        assert_eq!(
+            book(-5, &any, BookingMethod::Average),
+            Ok(vec![
+                lot(-10, dec!(85.20), date! {2023-02-01}),
+                lot(-10, dec!(90.00), date! {2023-01-01}),
+                lot(15, dec!(87.60), date! {2023-01-01}),
+            ])
+        );
+        assert_eq!(
+            book(-5, &any, BookingMethod::None),
+            Err(BookingError::IncompleteCost)
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use crate::{options::RootNames, types::AccountType};
+
+/// The kind of a [`Lexeme`]. The values borrow from the source and are only checked for their
+/// shape e.g. a date has the form `YYYY-MM-DD` but might not be a valid date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LexemeKind<'a> {
+    /// Spaces and tabs.
+    Whitespace,
+    /// A comment, from the `;` up to the end of the line.
+    Comment,
+    /// `\n` or `\r\n`.
+    Newline,
+    /// An option line, holding the name and the value of the option.
+    OptionLine {
+        name: &'a str,
+        value: &'a str,
+    },
+    Date(&'a str),
+    /// An amount, holding the number, which can contain commas, and the currency.
+    Amount {
+        number: &'a str,
+        currency: &'a str,
+    },
+    DirectiveOpen,
+    DirectiveBalance,
+    DirectiveClose,
+    DirectivePad,
+    DirectivePrice,
+    DirectiveInclude,
+    DirectivePostTx,
+    /// An account, holding the type of its root and the components after the root, which are
+    /// separated by colons.
+    Account {
+        type_: AccountType,
+        components: &'a str,
+    },
+    Currency(&'a str),
+    /// A double quoted string, holding its contents which haven't been unescaped.
+    TxDescription(&'a str),
+    At,
+    DoubleAt,
+    Comma,
+    LeftBrace,
+    RightBrace,
+    DoubleLeftBrace,
+    DoubleRightBrace,
+    /// A sequence of characters which isn't any of the above, up to the next whitespace.
+    Unknown,
+}
+
+/// A lexeme at the start of the source passed to [`lex`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Lexeme<'a> {
+    pub(crate) kind: LexemeKind<'a>,
+    /// The length of the lexeme in bytes.
+    pub(crate) len: usize,
+}
+
+impl<'a> Lexeme<'a> {
+    fn new(kind: LexemeKind<'a>, len: usize) -> Self {
+        Lexeme { kind, len }
+    }
+}
+
+/// The keywords which start directives.
+const KEYWORDS: [(&str, LexemeKind); 6] = [
+    ("open", LexemeKind::DirectiveOpen),
+    ("balance", LexemeKind::DirectiveBalance),
+    ("close", LexemeKind::DirectiveClose),
+    ("pad", LexemeKind::DirectivePad),
+    ("price", LexemeKind::DirectivePrice),
+    ("include", LexemeKind::DirectiveInclude),
+];
+
+/// Returns the lexeme at the start of `source`, which mustn't be empty. Accounts are only
+/// recognised if their root is one of `root_names`.
+///
+/// The kind of lexeme is chosen by the first byte, so each byte is only looked at by the rules
+/// which can match it. Words, dates, amounts and the like must be followed by whitespace or the
+/// end of the source, otherwise they're [`LexemeKind::Unknown`]. Amounts and currencies can also
+/// be followed by `,` or `}`, and dates by `}`. Digits are ASCII digits.
+pub(crate) fn lex<'a>(source: &'a str, root_names: &RootNames) -> Lexeme<'a> {
+    let bytes = source.as_bytes();
+    let lexeme = match bytes[0] {
+        b' ' | b'\t' => Some(Lexeme::new(
+            LexemeKind::Whitespace,
+            count_while(bytes, |b| b == b' ' || b == b'\t'),
+        )),
+        b'\n' => Some(Lexeme::new(LexemeKind::Newline, 1)),
+        b'\r' if bytes.get(1) == Some(&b'\n') => Some(Lexeme::new(LexemeKind::Newline, 2)),
+        b';' => Some(Lexeme::new(
+            LexemeKind::Comment,
+            count_while(bytes, |b| b != b'\r' && b != b'\n'),
+        )),
+        b'0'..=b'9' => lex_date(source).or_else(|| lex_amount(source)),
+        b'-' => lex_amount(source),
+        b'"' => lex_string(source),
+        b'*' => terminated(bytes, 1, b"").then_some(Lexeme::new(LexemeKind::DirectivePostTx, 1)),
+        b'@' if bytes.get(1) == Some(&b'@') && terminated(bytes, 2, b"") => {
+            Some(Lexeme::new(LexemeKind::DoubleAt, 2))
+        }
+        b'@' => terminated(bytes, 1, b"").then_some(Lexeme::new(LexemeKind::At, 1)),
+        b'{' if bytes.get(1) == Some(&b'{') => Some(Lexeme::new(LexemeKind::DoubleLeftBrace, 2)),
+        b'{' => Some(Lexeme::new(LexemeKind::LeftBrace, 1)),
+        b'}' if bytes.get(1) == Some(&b'}') => Some(Lexeme::new(LexemeKind::DoubleRightBrace, 2)),
+        b'}' => Some(Lexeme::new(LexemeKind::RightBrace, 1)),
+        b',' => Some(Lexeme::new(LexemeKind::Comma, 1)),
+        b'a'..=b'z' => lex_option(source).or_else(|| lex_keyword(source)),
+        _ => lex_account(source, root_names).or_else(|| lex_currency(source)),
+    };
+
+    lexeme.unwrap_or_else(|| {
+        let len = source.find(char::is_whitespace).unwrap_or(source.len());
+        Lexeme::new(LexemeKind::Unknown, len)
+    })
+}
+
+/// Returns `true` if `source` starts with a string or an option line which runs to the end of
+/// `source`, in which case [`lex`] could return a different lexeme if the source went on. Every
+/// other lexeme ends before the end of a line.
+pub(crate) fn is_truncated(source: &str) -> bool {
+    if source.starts_with('"') {
+        return matches!(string_end(source.as_bytes()), StringEnd::Truncated);
+    }
+
+    // Follows the steps of `lex_option`, looking for the point where it runs out of source
+    let Some(mut rest) = source.strip_prefix("option") else {
+        return false;
+    };
+    for _ in 0..2 {
+        let trimmed = rest.trim_start_matches(char::is_whitespace);
+        if trimmed.is_empty() {
+            return true;
+        }
+        if trimmed.len() == rest.len() {
+            return false;
+        }
+        let Some(quoted) = trimmed.strip_prefix('"') else {
+            return false;
+        };
+        let Some((_, after)) = quoted.split_once('"') else {
+            return true;
+        };
+        rest = after;
+    }
+    false
+}
+
+/// Returns the number of bytes at the start of `bytes` for which `predicate` holds.
+fn count_while(bytes: &[u8], predicate: impl Fn(u8) -> bool) -> usize {
+    bytes.iter().take_while(|b| predicate(**b)).count()
+}
+
+/// Returns `true` if the byte at `end` is whitespace, one of `also`, or the end of the source.
+fn terminated(bytes: &[u8], end: usize, also: &[u8]) -> bool {
+    bytes
+        .get(end)
+        .is_none_or(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r') || also.contains(b))
+}
+
+/// Lexes an option line e.g. `option "title" "My Accounts"`. The name and value can't be empty
+/// or contain double quotes.
+fn lex_option(source: &str) -> Option<Lexeme<'_>> {
+    let rest = source.strip_prefix("option")?;
+    let (name, rest) = strip_quoted(rest)?;
+    let (value, rest) = strip_quoted(rest)?;
+
+    let len = source.len() - rest.len();
+    terminated(source.as_bytes(), len, b"")
+        .then_some(Lexeme::new(LexemeKind::OptionLine { name, value }, len))
+}
+
+/// Strips whitespace, of which there must be some, followed by a double quoted string from the
+/// start of `source`. Returns the contents of the string and the rest of the source.
+fn strip_quoted(source: &str) -> Option<(&str, &str)> {
+    let rest = source.trim_start_matches(char::is_whitespace);
+    if rest.len() == source.len() {
+        return None;
+    }
+
+    let (contents, rest) = rest.strip_prefix('"')?.split_once('"')?;
+    (!contents.is_empty()).then_some((contents, rest))
+}
+
+fn lex_keyword(source: &str) -> Option<Lexeme<'_>> {
+    KEYWORDS.into_iter().find_map(|(keyword, kind)| {
+        (source.starts_with(keyword) && terminated(source.as_bytes(), keyword.len(), b""))
+            .then_some(Lexeme::new(kind, keyword.len()))
+    })
+}
+
+/// Lexes a date of the form `YYYY-MM-DD`.
+fn lex_date(source: &str) -> Option<Lexeme<'_>> {
+    let bytes = source.as_bytes();
+    let shape = bytes.get(..10)?.iter().enumerate().all(|(i, b)| match i {
+        4 | 7 => *b == b'-',
+        _ => b.is_ascii_digit(),
+    });
+
+    (shape && terminated(bytes, 10, b"}")).then(|| Lexeme::new(LexemeKind::Date(&source[..10]), 10))
+}
+
+/// Lexes a number, which can be negative, followed by a currency of at least three letters. The
+/// number and currency can be separated by spaces or tabs.
+fn lex_amount(source: &str) -> Option<Lexeme<'_>> {
+    let bytes = source.as_bytes();
+    let sign = usize::from(bytes[0] == b'-');
+    let number_end = sign
+        + count_while(&bytes[sign..], |b| {
+            b.is_ascii_digit() || b == b',' || b == b'.'
+        });
+    if !is_number(&source[sign..number_end]) {
+        return None;
+    }
+
+    let currency_start =
+        number_end + count_while(&bytes[number_end..], |b| b == b' ' || b == b'\t');
+    let currency_end =
+        currency_start + count_while(&bytes[currency_start..], |b| b.is_ascii_uppercase());
+    if currency_end - currency_start < 3 || !terminated(bytes, currency_end, b",}") {
+        return None;
+    }
+
+    let kind = LexemeKind::Amount {
+        number: &source[..number_end],
+        currency: &source[currency_start..currency_end],
+    };
+    Some(Lexeme::new(kind, currency_end))
+}
+
+/// Returns `true` if `number` is digits, which can be split into thousands with commas, followed
+/// by an optional decimal point and digits e.g. `1234`, `1,234` and `1,234.5`.
+fn is_number(number: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    let (integer, fraction) = match number.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (number, None),
+    };
+
+    let mut groups = integer.split(',');
+    let first = groups.next().unwrap_or_default();
+    let integer_valid = if integer.contains(',') {
+        digits(first) && first.len() <= 3 && groups.all(|g| digits(g) && g.len() == 3)
+    } else {
+        digits(integer)
+    };
+
+    integer_valid && fraction.is_none_or(digits)
+}
+
+/// Where a double quoted string at the start of the source ends.
+enum StringEnd {
+    /// At the closing quote, which is at this index.
+    Closed(usize),
+    /// At a backslash before a newline, which can't be escaped.
+    Invalid,
+    /// At the end of the source, before the string was closed.
+    Truncated,
+}
+
+/// Finds the end of the double quoted string at the start of `bytes`. Inside the string a
+/// backslash escapes the character after it, which can't be a newline.
+fn string_end(bytes: &[u8]) -> StringEnd {
+    let mut end = 1;
+    loop {
+        match (bytes.get(end), bytes.get(end + 1)) {
+            (None, _) | (Some(b'\\'), None) => return StringEnd::Truncated,
+            (Some(b'"'), _) => return StringEnd::Closed(end),
+            (Some(b'\\'), Some(b'\n')) => return StringEnd::Invalid,
+            // The escaped character can be more than one byte, in which case the next bytes are
+            // continuation bytes, which are never a quote or a backslash.
+            (Some(b'\\'), _) => end += 2,
+            _ => end += 1,
+        }
+    }
+}
+
+/// Lexes a double quoted string.
+fn lex_string(source: &str) -> Option<Lexeme<'_>> {
+    let bytes = source.as_bytes();
+    let StringEnd::Closed(end) = string_end(bytes) else {
+        return None;
+    };
+
+    terminated(bytes, end + 1, b"")
+        .then(|| Lexeme::new(LexemeKind::TxDescription(&source[1..end]), end + 1))
+}
+
+/// Lexes an account, whose root is one of `root_names` and whose components each start with a
+/// capital letter or a digit e.g. `Assets:Bank:2024:Checking`.
+fn lex_account<'a>(source: &'a str, root_names: &RootNames) -> Option<Lexeme<'a>> {
+    let root_end = source
+        .find(|c: char| !c.is_alphanumeric() && c != '-')
+        .unwrap_or(source.len());
+    let root = &source[..root_end];
+    if !root.chars().next()?.is_uppercase() {
+        return None;
+    }
+    let type_ = root_names.account_type(root)?;
+
+    let bytes = source.as_bytes();
+    let mut end = root_end;
+    while bytes.get(end) == Some(&b':')
+        && bytes
+            .get(end + 1)
+            .is_some_and(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+    {
+        end += 2 + count_while(&bytes[end + 2..], |b| {
+            b.is_ascii_alphanumeric() || b == b'-'
+        });
+    }
+
+    (end > root_end && terminated(bytes, end, b"")).then(|| {
+        let components = &source[root_end + 1..end];
+        Lexeme::new(LexemeKind::Account { type_, components }, end)
+    })
+}
+
+/// Lexes a currency, which is made of capital letters.
+fn lex_currency(source: &str) -> Option<Lexeme<'_>> {
+    let bytes = source.as_bytes();
+    let end = count_while(bytes, |b| b.is_ascii_uppercase());
+
+    (end > 0 && terminated(bytes, end, b",}"))
+        .then(|| Lexeme::new(LexemeKind::Currency(&source[..end]), end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the kinds and texts of the lexemes in `source`.
+    fn lex_all(source: &str) -> Vec<(LexemeKind<'_>, &str)> {
+        let root_names = RootNames::default();
+        let mut lexemes = vec![];
+        let mut rest = source;
+        while !rest.is_empty() {
+            let lexeme = lex(rest, &root_names);
+            // Unknown sequences can be empty, step over them as the tokenizer would
+            let len = lexeme.len.max(1);
+            lexemes.push((lexeme.kind, &rest[..lexeme.len]));
+            rest = &rest[len..];
+        }
+        lexemes
+    }
+
+    #[test]
+    fn lex_works() {
+        use LexemeKind::*;
+
+        assert_eq!(
+            lex_all(
+                "2023-02-01 * \"Caf\\\"é\" ; note\r\n  Assets:Bank:2024  -1,234.5 GBP @@ 1 USD"
+            ),
+            vec![
+                (Date("2023-02-01"), "2023-02-01"),
+                (Whitespace, " "),
+                (DirectivePostTx, "*"),
+                (Whitespace, " "),
+                (TxDescription("Caf\\\"é"), "\"Caf\\\"é\""),
+                (Whitespace, " "),
+                (Comment, "; note"),
+                (Newline, "\r\n"),
+                (Whitespace, "  "),
+                (
+                    Account {
+                        type_: AccountType::Asset,
+                        components: "Bank:2024"
+                    },
+                    "Assets:Bank:2024"
+                ),
+                (Whitespace, "  "),
+                (
+                    Amount {
+                        number: "-1,234.5",
+                        currency: "GBP"
+                    },
+                    "-1,234.5 GBP"
+                ),
+                (Whitespace, " "),
+                (DoubleAt, "@@"),
+                (Whitespace, " "),
+                (
+                    Amount {
+                        number: "1",
+                        currency: "USD"
+                    },
+                    "1 USD"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_sequences() {
+        for (source, len) in [
+            ("1,23 GBP", 4),
+            ("1. GBP", 2),
+            ("12 GB", 2),
+            ("2023-02-01,", 11),
+            ("opened", 6),
+            ("Assets:bank", 11),
+            ("Assetz:Bank", 11),
+            ("\"unterminated", 13),
+            ("\"a\"b", 4),
+            ("@@@", 3),
+            ("GBp", 3),
+            ("\r", 0),
+        ] {
+            assert_eq!(
+                lex(source, &RootNames::default()),
+                Lexeme::new(LexemeKind::Unknown, len),
+                "{source}"
+            );
+        }
+    }
+}
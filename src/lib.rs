@@ -1,4 +1,13 @@
 pub mod accounts_doc;
+pub mod cgt;
+pub mod cst;
+pub mod diagnostic;
+pub mod inventory;
+mod lexer;
+pub mod line_index;
+pub mod loader;
+pub mod options;
 pub mod parser;
+pub mod prices;
 pub mod tokenizer;
 pub mod types;
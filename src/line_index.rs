@@ -0,0 +1,154 @@
+use std::ops::Range;
+
+/// A one-indexed position in a source, found by [`LineIndex`]. The column is given in each of the
+/// units tools count in: bytes, characters i.e. Unicode scalar values, and UTF-16 code units, the
+/// last of which is what most editors use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    line: usize,
+    byte_column: usize,
+    char_column: usize,
+    utf16_column: usize,
+}
+
+impl Position {
+    /// Returns the one-indexed line number.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the one-indexed column, counting the bytes before it on the line.
+    pub fn byte_column(&self) -> usize {
+        self.byte_column
+    }
+
+    /// Returns the one-indexed column, counting the characters before it on the line. This is
+    /// the column of a [`Token`](crate::tokenizer::Token).
+    pub fn char_column(&self) -> usize {
+        self.char_column
+    }
+
+    /// Returns the one-indexed column, counting the UTF-16 code units before it on the line.
+    pub fn utf16_column(&self) -> usize {
+        self.utf16_column
+    }
+}
+
+/// Converts byte offsets in a source, such as the spans of tokens, into [`Position`]s. The start
+/// of every line is found when the index is created, so each conversion only looks at the line
+/// the offset is on.
+///
+/// The spans of sources loaded with [`load`](crate::loader::load) are offset for each file, so
+/// they should be made relative to their file with
+/// [`SourceMap::lookup`](crate::loader::SourceMap::lookup) first.
+///
+/// # Examples
+///
+/// ```
+/// use recount::line_index::LineIndex;
+///
+/// let source = "2023-02-01 * \"Café Nerö\" \"☕\"\n";
+/// let index = LineIndex::new(source);
+/// let end = index.position(source.len() - 1);
+///
+/// assert_eq!((end.line(), end.byte_column(), end.char_column()), (1, 33, 29));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+    // The byte offset of the start of each line, the first of which is zero
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    /// LineIndex constructor.
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex {
+            source,
+            line_starts,
+        }
+    }
+
+    /// Returns the position of the byte at `offset`. A newline is on the line it ends. Offsets
+    /// inside a character are treated as the start of the character, and offsets past the end of
+    /// the source as the end of the source.
+    pub fn position(&self, offset: usize) -> Position {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let before = &self.source[self.line_starts[line - 1]..offset];
+
+        Position {
+            line,
+            byte_column: before.len() + 1,
+            char_column: before.chars().count() + 1,
+            utf16_column: before.encode_utf16().count() + 1,
+        }
+    }
+
+    /// Returns the positions of the start and the end of `span`.
+    pub fn span(&self, span: Range<usize>) -> Range<Position> {
+        self.position(span.start)..self.position(span.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Tokenizer;
+
+    #[test]
+    fn position_works() {
+        let source = "2023-02-01 * \"Café Nerö\" \"🥛\"\r\n  Assets:Café\n";
+        let index = LineIndex::new(source);
+        let columns = |offset| {
+            let position = index.position(offset);
+            (
+                position.line(),
+                position.byte_column(),
+                position.char_column(),
+                position.utf16_column(),
+            )
+        };
+
+        assert_eq!(columns(0), (1, 1, 1, 1));
+        // The narration, after the payee's two accented characters
+        assert_eq!(columns(27), (1, 28, 26, 26));
+        // The carriage return, after the emoji which is two UTF-16 code units
+        assert_eq!(columns(33), (1, 34, 29, 30));
+        assert_eq!(columns(34), (1, 35, 30, 31));
+        assert_eq!(columns(35), (2, 1, 1, 1));
+        // Inside the `é` of the account
+        assert_eq!(columns(48), (2, 13, 13, 13));
+        assert_eq!(columns(47), (2, 13, 13, 13));
+        assert_eq!(columns(source.len()), (3, 1, 1, 1));
+        assert_eq!(columns(source.len() + 10), (3, 1, 1, 1));
+
+        assert_eq!(index.span(13..26), index.position(13)..index.position(26));
+    }
+
+    #[test]
+    fn token_columns_are_char_columns() {
+        let source = "option \"title\" \"Nerö\"\n2023-02-01 * \"Café Nerö\" \"🥛\"\n  Assets:Cash  -3.50 GBP ; é\n  Expenses:Coffee\n";
+        let index = LineIndex::new(source);
+
+        let mut tokens = 0;
+        for token in Tokenizer::new(source) {
+            let token = token.unwrap();
+            let position = index.position(token.span.start);
+            assert_eq!(
+                (token.line, token.column),
+                (position.line(), position.char_column()),
+                "{token:?}"
+            );
+            tokens += 1;
+        }
+        assert_eq!(tokens, 12);
+    }
+}
@@ -0,0 +1,700 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs, io,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    accounts_doc::AccountsDocument,
+    diagnostic::Diagnostic,
+    parser::{ParseError, parse_with_recovery},
+    tokenizer::{Token, TokenKind, TokenizeError, Tokenizer},
+};
+
+/// A file loaded by [`load`]. The spans of the tokens in the file are offset by `base`, so that
+/// every file has its own range of spans.
+#[derive(Debug)]
+pub struct SourceFile {
+    path: PathBuf,
+    source: String,
+    base: usize,
+}
+
+impl SourceFile {
+    /// Returns the path of the file, relative to the directory of the file passed to [`load`] if
+    /// that path was relative.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the contents of the file.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+/// The files loaded by [`load`], in the order they were loaded. Used to find the file a
+/// [`ParseError`] is in from its span.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// Returns the files loaded.
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Returns the file containing the start of `span`, along with the span relative to the start
+    /// of the file.
+    pub fn lookup(&self, span: Range<usize>) -> Option<(&SourceFile, Range<usize>)> {
+        let index = self
+            .files
+            .partition_point(|file| file.base <= span.start)
+            .checked_sub(1)?;
+        let file = &self.files[index];
+
+        Some((file, span.start - file.base..span.end - file.base))
+    }
+
+    /// Renders `error` as a [`Diagnostic`] showing the name of the file it's in.
+    pub fn render(&self, error: &ParseError) -> String {
+        let diagnostic = Diagnostic::from(error);
+        match self.lookup(error.span()) {
+            Some((file, span)) => diagnostic
+                .with_span(span)
+                .render(&file.path.display().to_string(), &file.source),
+            None => diagnostic.render("<unknown>", ""),
+        }
+    }
+
+    /// Adds a file, returning its index.
+    fn add(&mut self, path: PathBuf, source: String) -> usize {
+        // Leave a gap of one after each file so that a span at the end of a file isn't in the next
+        let base = self
+            .files
+            .last()
+            .map_or(0, |file| file.base + file.source.len() + 1);
+        self.files.push(SourceFile { path, source, base });
+        self.files.len() - 1
+    }
+}
+
+/// Loads the Beancount file at `path`, along with the files it includes, into an
+/// [`AccountsDocument`]. Include directives take the form:
+/// ```beancount
+/// include "accounts/*.beancount"
+/// ```
+/// The path is relative to the directory of the file containing the directive, and can contain
+/// wildcards, see [`expand_glob`]. The directives in the included files are added at the point
+/// of the include directive. Each file is only loaded once, so a file which is included again,
+/// such as one matched by two includes, is skipped. A file which includes itself, directly or
+/// through other files, is reported as an error. Options such as `name_assets` apply to the
+/// files loaded after them, whichever file they're in.
+///
+/// Returns an [`io::Error`] only if the file at `path` can't be read. All other errors, including
+/// files which can't be included, are returned as [`ParseError`]s whose spans can be mapped back
+/// to their file with the returned [`SourceMap`].
+pub fn load(path: impl AsRef<Path>) -> io::Result<(AccountsDocument, Vec<ParseError>, SourceMap)> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)?;
+    let canonical = path.canonicalize()?;
+
+    let mut sources = SourceMap::default();
+    let mut tokens = IncludeTokens {
+        stack: vec![],
+        pending: VecDeque::new(),
+        sources: &mut sources,
+        loaded: HashSet::new(),
+    };
+    let frame = tokens.frame(path.to_path_buf(), canonical, source, Tokenizer::new);
+    tokens.stack.push(frame);
+    let (accounts_doc, errors) = parse_with_recovery(&mut tokens);
+
+    Ok((accounts_doc, errors, sources))
+}
+
+/// A file which is being tokenized, or which is waiting to be tokenized after the files before it
+/// which matched the same include.
+struct Frame {
+    file: usize,
+    canonical: PathBuf,
+    tokenizer: Tokenizer,
+    line_start: bool,
+    // Whether tokenizing the file has started, in which case it's one of the files including the
+    // file being tokenized, or the file itself
+    active: bool,
+}
+
+/// The tokens of a file with the tokens of the files it includes spliced in, in place of the
+/// include directives.
+struct IncludeTokens<'a> {
+    stack: Vec<Frame>, // the file being tokenized is last, preceded by the files including it
+    pending: VecDeque<Result<Token, TokenizeError>>,
+    sources: &'a mut SourceMap,
+    // The canonical paths of the files added to the source map
+    loaded: HashSet<PathBuf>,
+}
+
+impl IncludeTokens<'_> {
+    /// Adds the file at `path` to the source map, returning a frame to tokenize it with the
+    /// tokenizer created by `tokenizer`.
+    fn frame(
+        &mut self,
+        path: PathBuf,
+        canonical: PathBuf,
+        source: String,
+        tokenizer: impl FnOnce(String) -> Tokenizer,
+    ) -> Frame {
+        let file = self.sources.add(path, source.clone());
+        self.loaded.insert(canonical.clone());
+        Frame {
+            file,
+            canonical,
+            tokenizer: tokenizer(source),
+            line_start: true,
+            active: false,
+        }
+    }
+
+    /// Returns the next token of the file being tokenized, with its span offset so that it's in
+    /// the file's range of spans.
+    fn next_in_file(&mut self) -> Option<Result<Token, TokenizeError>> {
+        let frame = self.stack.last_mut()?;
+        frame.active = true;
+        let base = self.sources.files[frame.file].base;
+        let item = frame.tokenizer.next()?.map_or_else(
+            |mut error| {
+                error.span = error.span.start + base..error.span.end + base;
+                Err(error)
+            },
+            |mut token| {
+                token.span = token.span.start + base..token.span.end + base;
+                Ok(token)
+            },
+        );
+        frame.line_start = matches!(
+            item,
+            Ok(Token {
+                kind: TokenKind::Newline,
+                ..
+            })
+        );
+
+        Some(item)
+    }
+
+    /// Handles the rest of an include directive, which starts with `include`. The tokens of the
+    /// included files come after the newline at the end of the directive. Any errors are added to
+    /// the pending tokens, ahead of the newline.
+    fn include(&mut self, include: Token) {
+        let (pattern, path_token) = match self.next_in_file() {
+            Some(Ok(token)) => match &token.kind {
+                TokenKind::TxDescription(pattern) => (pattern.clone(), token),
+                _ => {
+                    let message =
+                        format!("expected a quoted path after include, found {}", token.kind);
+                    self.pending.push_back(Err(error_at(&token, message)));
+                    self.pending.push_back(Ok(token));
+                    return;
+                }
+            },
+            Some(Err(error)) => {
+                self.pending.push_back(Err(error));
+                return;
+            }
+            None => {
+                let message = "expected a quoted path after include, found end of file";
+                self.pending.push_back(Err(error_at(&include, message)));
+                return;
+            }
+        };
+
+        let newline = match self.next_in_file() {
+            None => None,
+            Some(Ok(
+                token @ Token {
+                    kind: TokenKind::Newline,
+                    ..
+                },
+            )) => Some(token),
+            Some(Ok(token)) => {
+                let message = format!(
+                    "expected newline after the included path, found {}",
+                    token.kind
+                );
+                self.pending.push_back(Err(error_at(&token, message)));
+                self.pending.push_back(Ok(token));
+                return;
+            }
+            Some(Err(error)) => {
+                self.pending.push_back(Err(error));
+                return;
+            }
+        };
+
+        self.include_files(&pattern, &path_token);
+        self.pending.extend(newline.map(Ok));
+    }
+
+    /// Starts tokenizing the files matching `pattern`, which is relative to the directory of the
+    /// file being tokenized. Errors are positioned at `path_token`.
+    fn include_files(&mut self, pattern: &str, path_token: &Token) {
+        let frame = self
+            .stack
+            .last()
+            .expect("the include directive is in a file");
+        let root_names = frame.tokenizer.root_names().clone();
+        let dir = self.sources.files[frame.file]
+            .path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+
+        let paths = match expand_glob(&dir, pattern) {
+            Ok(paths) if paths.is_empty() => {
+                let message = format!("no files match \"{pattern}\"");
+                self.pending.push_back(Err(error_at(path_token, message)));
+                return;
+            }
+            Ok(paths) => paths,
+            Err(e) => {
+                let message = format!("can't include \"{pattern}\": {e}");
+                self.pending.push_back(Err(error_at(path_token, message)));
+                return;
+            }
+        };
+
+        let mut files = vec![];
+        for path in paths {
+            let file = path.canonicalize().and_then(|canonical| {
+                let source = fs::read_to_string(&path)?;
+                Ok((canonical, source))
+            });
+            match file {
+                // Only the files including this one make a cycle, the files waiting on the stack
+                // are included alongside it
+                Ok((canonical, _))
+                    if self
+                        .stack
+                        .iter()
+                        .any(|f| f.active && f.canonical == canonical) =>
+                {
+                    let message = format!("\"{}\" is already being included", path.display());
+                    self.pending.push_back(Err(error_at(path_token, message)));
+                }
+                // Files which have been loaded, or are waiting on the stack, aren't loaded again
+                Ok((canonical, _)) if self.loaded.contains(&canonical) => {}
+                Ok((canonical, source)) => files.push((path, canonical, source)),
+                Err(e) => {
+                    let message = format!("can't include \"{}\": {e}", path.display());
+                    self.pending.push_back(Err(error_at(path_token, message)));
+                }
+            }
+        }
+
+        let frames: Vec<_> = files
+            .into_iter()
+            .map(|(path, canonical, source)| {
+                let root_names = root_names.clone();
+                self.frame(path, canonical, source, |source| {
+                    Tokenizer::with_root_names(source, root_names)
+                })
+            })
+            .collect();
+        // The first file is pushed last so that it's tokenized first
+        self.stack.extend(frames.into_iter().rev());
+    }
+}
+
+impl Iterator for IncludeTokens<'_> {
+    type Item = Result<Token, TokenizeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+
+            match self.next_in_file() {
+                Some(Ok(
+                    token @ Token {
+                        kind: TokenKind::DirectiveInclude,
+                        ..
+                    },
+                )) if token.column == 1 => self.include(token),
+                Some(item) => return Some(item),
+                None => {
+                    let frame = self.stack.pop()?;
+                    // The file tokenized next recognises the root names set in this one
+                    if let Some(next) = self.stack.last_mut() {
+                        next.tokenizer
+                            .set_root_names(frame.tokenizer.root_names().clone());
+                    }
+                    // An included file is ended with a blank line, so that a transaction at the
+                    // end of the file ends there and the next directive starts on a new line
+                    if !self.stack.is_empty() {
+                        let file = &self.sources.files[frame.file];
+                        if !frame.line_start {
+                            self.pending.push_back(Ok(end_of_file(file)));
+                        }
+                        self.pending.push_back(Ok(end_of_file(file)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns a newline token positioned at the end of `file`.
+fn end_of_file(file: &SourceFile) -> Token {
+    let end = file.base + file.source.len();
+    let last_line = file.source.rsplit('\n').next().unwrap_or_default();
+
+    Token {
+        kind: TokenKind::Newline,
+        line: file.source.matches('\n').count() + 1,
+        column: last_line.chars().count() + 1,
+        span: end..end,
+    }
+}
+
+/// Returns an error with `message` positioned at `token`.
+fn error_at(token: &Token, message: impl Into<String>) -> TokenizeError {
+    TokenizeError {
+        msg: message.into(),
+        line: token.line,
+        column: token.column,
+        span: token.span.clone(),
+    }
+}
+
+/// Returns the files matching `pattern`, a path which is relative to `dir` unless it's absolute.
+/// Each component of the path can contain wildcards: `*` matches any sequence of characters and
+/// `?` matches any single character, but neither matches a `.` at the start of a file name. The
+/// files matching a component are sorted by name.
+pub fn expand_glob(dir: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![dir.to_path_buf()];
+
+    for component in Path::new(pattern).components() {
+        let name = match component {
+            Component::Normal(name) => name.to_string_lossy(),
+            _ => {
+                paths = paths.iter().map(|path| path.join(component)).collect();
+                continue;
+            }
+        };
+
+        if !name.contains(['*', '?']) {
+            paths = paths.iter().map(|path| path.join(&*name)).collect();
+            continue;
+        }
+
+        let name_pattern: Vec<char> = name.chars().collect();
+        let mut matches = vec![];
+        for path in &paths {
+            // Paths which aren't directories don't contain any matches
+            let Ok(entries) = fs::read_dir(path) else {
+                continue;
+            };
+            let mut entries = entries
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.sort();
+
+            for entry in entries {
+                let entry_name: Vec<char> = entry.to_string_lossy().chars().collect();
+                let hidden = entry_name.first() == Some(&'.') && name_pattern.first() != Some(&'.');
+                if !hidden && wildcard_matches(&name_pattern, &entry_name) {
+                    matches.push(path.join(entry));
+                }
+            }
+        }
+        paths = matches;
+    }
+
+    Ok(paths.into_iter().filter(|path| path.is_file()).collect())
+}
+
+/// Returns `true` if `name` matches `pattern`, in which `*` matches any sequence of characters and
+/// `?` matches any single character.
+///
+/// Only the last `*` is ever backtracked to, since whatever an earlier `*` matches a later one can
+/// match instead, so this takes time proportional to the length of `pattern` times the length of
+/// `name`.
+fn wildcard_matches(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The position in `pattern` after the last `*`, and the position in `name` it matches up to
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            // Let the last `*` match one more character, and carry on from after it
+            _ => match star {
+                Some((after_star, matched)) => {
+                    p = after_star;
+                    n = matched + 1;
+                    star = Some((after_star, n));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountId, AccountType};
+
+    /// Creates an empty directory for a test, named `name`, in the temporary directory.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("recount-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn wildcard_matches_works() {
+        let matches = |pattern: &str, name: &str| {
+            wildcard_matches(
+                &pattern.chars().collect::<Vec<_>>(),
+                &name.chars().collect::<Vec<_>>(),
+            )
+        };
+
+        assert!(matches("*.beancount", "2023.beancount"));
+        assert!(matches("*.beancount", ".beancount"));
+        assert!(matches("20?3-*.beancount", "2023-bank.beancount"));
+        assert!(matches("*", ""));
+        assert!(!matches("*.beancount", "2023.bean"));
+        assert!(!matches("?.beancount", ".beancount"));
+        assert!(!matches("2023", "2023.beancount"));
+        assert!(matches("*a*b?", "xaybabc"));
+        assert!(!matches("*a*b?", "xaybab"));
+        assert!(matches("a*", "a"));
+        assert!(!matches("*a*b", "xaybxa"));
+
+        // Each `*` doesn't multiply the work of the ones before it
+        let name = "a".repeat(1000);
+        assert!(!matches(&format!("{}b", "*a".repeat(20)), &name));
+        assert!(matches(&format!("{}*", "*a".repeat(20)), &name));
+    }
+
+    #[test]
+    fn includes_work() {
+        let dir = test_dir("includes");
+        fs::create_dir(dir.join("accounts")).unwrap();
+        fs::write(
+            dir.join("main.beancount"),
+            "option \"title\" \"Main\"\ninclude \"accounts/*.beancount\"\n\
+             include \"transactions.beancount\"\n2023-02-05 balance Assets:Bank -3.20 GBP\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("accounts/a.beancount"),
+            "2023-02-01 open Assets:Bank GBP",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("accounts/b.beancount"),
+            "2023-02-01 open Expenses:Food GBP\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("accounts/.hidden.beancount"),
+            "2023-02-01 open Expenses:Food GBP\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("transactions.beancount"),
+            "2023-02-03 * \"Lunch\"\n  Expenses:Food  3.20 GBP\n  Assets:Bank",
+        )
+        .unwrap();
+
+        let (accts, errors, sources) = load(dir.join("main.beancount")).unwrap();
+
+        assert_eq!(errors, vec![]);
+        assert_eq!(
+            sources
+                .files()
+                .iter()
+                .map(|f| f.path().strip_prefix(&dir).unwrap().to_path_buf())
+                .collect::<Vec<_>>(),
+            [
+                "main.beancount",
+                "accounts/a.beancount",
+                "accounts/b.beancount",
+                "transactions.beancount"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            accts
+                .balance(&AccountId {
+                    components: vec!["Food".to_string()],
+                    type_: AccountType::Expense,
+                })
+                .expect("the account exists")
+                .to_string(),
+            "3.20 GBP"
+        );
+        assert_eq!(accts.balance_assertions().len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_matched_twice() {
+        // `2023.beancount` includes `accounts.beancount`, which also matches the glob which
+        // includes `2023.beancount`, and is waiting to be tokenized after it. That isn't a cycle,
+        // and the accounts are only opened once.
+        let dir = test_dir("include-matched-twice");
+        fs::create_dir(dir.join("ledger")).unwrap();
+        fs::write(
+            dir.join("main.beancount"),
+            "option \"title\" \"Main\"\ninclude \"ledger/*.beancount\"\n\
+             include \"ledger/accounts.beancount\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("ledger/2023.beancount"),
+            "include \"accounts.beancount\"\n\
+             2023-02-03 * \"Lunch\"\n  Expenses:Food  3.20 GBP\n  Assets:Bank\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("ledger/accounts.beancount"),
+            "2023-02-01 open Assets:Bank GBP\n2023-02-01 open Expenses:Food GBP\n",
+        )
+        .unwrap();
+
+        let (accts, errors, sources) = load(dir.join("main.beancount")).unwrap();
+
+        assert_eq!(errors, vec![]);
+        assert_eq!(
+            sources
+                .files()
+                .iter()
+                .map(|f| f.path().strip_prefix(&dir).unwrap().to_path_buf())
+                .collect::<Vec<_>>(),
+            [
+                "main.beancount",
+                "ledger/2023.beancount",
+                "ledger/accounts.beancount",
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            accts
+                .balance(&AccountId {
+                    components: vec!["Food".to_string()],
+                    type_: AccountType::Expense,
+                })
+                .expect("the account exists")
+                .to_string(),
+            "3.20 GBP"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn options_in_included_files() {
+        let dir = test_dir("options-in-included-files");
+        fs::write(
+            dir.join("main.beancount"),
+            "option \"title\" \"Main\"\ninclude \"opts.beancount\"\n\
+             2023-01-01 open Actifs:Bank GBP\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("opts.beancount"),
+            "option \"name_assets\" \"Actifs\"\n",
+        )
+        .unwrap();
+
+        let (accts, errors, _) = load(dir.join("main.beancount")).unwrap();
+
+        assert_eq!(errors, vec![]);
+        assert_eq!(
+            accts.options().root_names().name(AccountType::Asset),
+            "Actifs"
+        );
+        assert!(
+            accts
+                .balance(&AccountId {
+                    components: vec!["Bank".to_string()],
+                    type_: AccountType::Asset,
+                })
+                .is_some()
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_errors() {
+        let dir = test_dir("include-errors");
+        fs::write(
+            dir.join("main.beancount"),
+            "option \"title\" \"Main\"\ninclude \"a.beancount\"\ninclude \"missing/*.beancount\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("a.beancount"),
+            "include \"main.beancount\"\n2023-02-01 open Assets:Bank GBP,\n",
+        )
+        .unwrap();
+
+        let (accts, errors, sources) = load(dir.join("main.beancount")).unwrap();
+
+        let a = dir.join("a.beancount").display().to_string();
+        let main = dir.join("main.beancount").display().to_string();
+        assert_eq!(
+            errors.iter().map(|e| sources.render(e)).collect::<Vec<_>>(),
+            vec![
+                format!(
+                    "error: \"{main}\" is already being included
+ --> {a}:1:9
+  |
+1 | include \"main.beancount\"
+  |         ^^^^^^^^^^^^^^^^
+"
+                ),
+                format!(
+                    "error: expected currency, found newline
+ --> {a}:2:33
+  |
+2 | 2023-02-01 open Assets:Bank GBP,
+  |                                 ^
+"
+                ),
+                format!(
+                    "error: no files match \"missing/*.beancount\"
+ --> {main}:3:9
+  |
+3 | include \"missing/*.beancount\"
+  |         ^^^^^^^^^^^^^^^^^^^^^
+"
+                ),
+            ]
+        );
+        assert_eq!(accts.options().title(), Some("Main"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
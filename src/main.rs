@@ -1,18 +1,27 @@
 use clap::Parser;
 use std::env::args;
+use std::io::stdin;
 use std::path::PathBuf;
-use std::{fs::read_to_string, process::ExitCode};
+use std::process::ExitCode;
 
-use recount::{parser::parse, tokenizer::Tokenizer};
+use recount::{
+    cgt::CgtReport, diagnostic::Diagnostic, loader::load, parser::parse_with_recovery,
+    tokenizer::Tokenizer,
+};
 
 const CRATE_NAME: &str = env!("CARGO_PKG_NAME");
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// The Beancount input filename to load
+    /// The Beancount input filename to load, or `-` to read from standard input e.g. to check
+    /// a compressed ledger with `gunzip -c ledger.beancount.gz | recount -f -`
     #[arg(short, long, value_name = "FILE")]
     file: PathBuf,
+
+    /// Print a UK capital gains report, by tax year, instead of the balances
+    #[arg(long)]
+    cgt: bool,
 }
 
 fn main() -> ExitCode {
@@ -29,11 +38,36 @@ fn main() -> ExitCode {
 fn run() -> Result<(), String> {
     let cli = Cli::parse();
 
-    let buffer = read_to_string(&cli.file)
-        .map_err(|e| format!("cannot access '{}': {}", cli.file.display(), e))?;
+    let (accounts_doc, errors) = if cli.file.as_os_str() == "-" {
+        // Standard input is tokenized as it's read, so the diagnostics can't show the source
+        // lines. Include directives aren't supported since there's no directory to include from.
+        let (accounts_doc, errors) = parse_with_recovery(Tokenizer::from_reader(stdin().lock()));
+        for error in &errors {
+            eprintln!("{}", Diagnostic::from(error).render("<stdin>", ""));
+        }
+        (accounts_doc, errors)
+    } else {
+        let (accounts_doc, errors, sources) = load(&cli.file)
+            .map_err(|e| format!("cannot access '{}': {}", cli.file.display(), e))?;
+        for error in &errors {
+            eprintln!("{}", sources.render(error));
+        }
+        (accounts_doc, errors)
+    };
 
-    let tokenizer = Tokenizer::new(buffer);
-    let accounts_doc = parse(tokenizer).map_err(|e| format!("parsing error: {}", e))?;
+    // Warnings are reported but don't stop the accounts from being used
+    let error_count = errors.iter().filter(|e| !e.is_warning()).count();
+    if error_count > 0 {
+        return Err(format!("{} errors found", error_count));
+    }
+
+    if cli.cgt {
+        let report = CgtReport::new(&accounts_doc).map_err(|e| e.to_string())?;
+        for tax_year in report.tax_years() {
+            println!("{}", tax_year);
+        }
+        return Ok(());
+    }
 
     for (account, balance) in accounts_doc.balances() {
         println!("{:?}", account);
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::types::{AccountType, BookingMethod};
+
+/// The tolerances used for amounts whose tolerance can't be inferred, because they're integers.
+/// Tolerances are otherwise inferred from the precision of the amounts, in line with Beancount.
+/// They're set with the `inferred_tolerance_default` option, which takes the form `JPY:1` for one
+/// currency or `*:0.005` for every other currency. Unless it's set the tolerance is zero, so
+/// integer amounts must balance exactly.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToleranceDefaults {
+    default: Decimal,
+    currencies: HashMap<String, Decimal>,
+}
+
+impl ToleranceDefaults {
+    /// Returns the default tolerance for `currency`.
+    pub fn get(&self, currency: &str) -> Decimal {
+        self.currencies
+            .get(currency)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Sets the default tolerance for `currency`, or for every currency without its own default
+    /// if `currency` is [`None`].
+    pub fn set(&mut self, currency: Option<&str>, tolerance: Decimal) {
+        match currency {
+            Some(currency) => {
+                self.currencies.insert(currency.to_string(), tolerance);
+            }
+            None => self.default = tolerance,
+        }
+    }
+}
+
+/// The names of the five root accounts, which default to `Assets`, `Liabilities`, `Equity`,
+/// `Income` and `Expenses`. They're set with the `name_assets`, `name_liabilities`,
+/// `name_equity`, `name_income` and `name_expenses` options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootNames {
+    assets: String,
+    liabilities: String,
+    equity: String,
+    income: String,
+    expenses: String,
+}
+
+impl Default for RootNames {
+    fn default() -> Self {
+        RootNames {
+            assets: "Assets".to_string(),
+            liabilities: "Liabilities".to_string(),
+            equity: "Equity".to_string(),
+            income: "Income".to_string(),
+            expenses: "Expenses".to_string(),
+        }
+    }
+}
+
+impl RootNames {
+    /// Returns the name of the root account of `type_`.
+    pub fn name(&self, type_: AccountType) -> &str {
+        match type_ {
+            AccountType::Asset => &self.assets,
+            AccountType::Liability => &self.liabilities,
+            AccountType::Equity => &self.equity,
+            AccountType::Income => &self.income,
+            AccountType::Expense => &self.expenses,
+        }
+    }
+
+    /// Returns the type of the root account named `name`, or [`None`] if `name` isn't the name of
+    /// a root account.
+    pub fn account_type(&self, name: &str) -> Option<AccountType> {
+        [
+            AccountType::Asset,
+            AccountType::Liability,
+            AccountType::Equity,
+            AccountType::Income,
+            AccountType::Expense,
+        ]
+        .into_iter()
+        .find(|type_| self.name(*type_) == name)
+    }
+
+    /// Sets a root name if `option` is one of the `name_*` options. Returns [`None`] if it isn't,
+    /// otherwise returns whether `value` was a valid name which isn't the name of another root
+    /// account.
+    pub(crate) fn set(&mut self, option: &str, value: &str) -> Option<Result<(), OptionError>> {
+        let type_ = match option {
+            "name_assets" => AccountType::Asset,
+            "name_liabilities" => AccountType::Liability,
+            "name_equity" => AccountType::Equity,
+            "name_income" => AccountType::Income,
+            "name_expenses" => AccountType::Expense,
+            _ => return None,
+        };
+
+        // A root name is a capitalised word, which can contain digits and hyphens
+        let valid = value.chars().next().is_some_and(char::is_uppercase)
+            && value.chars().all(|c| c.is_alphanumeric() || c == '-');
+        if !valid {
+            return Some(Err(OptionError::InvalidValue {
+                name: option.to_string(),
+                value: value.to_string(),
+            }));
+        }
+
+        // Two root accounts with the same name couldn't be told apart
+        if self.account_type(value).is_some_and(|other| other != type_) {
+            return Some(Err(OptionError::DuplicateRootName {
+                name: option.to_string(),
+                value: value.to_string(),
+            }));
+        }
+
+        let name = match type_ {
+            AccountType::Asset => &mut self.assets,
+            AccountType::Liability => &mut self.liabilities,
+            AccountType::Equity => &mut self.equity,
+            AccountType::Income => &mut self.income,
+            AccountType::Expense => &mut self.expenses,
+        };
+        *name = value.to_string();
+        Some(Ok(()))
+    }
+}
+
+/// The options set with option lines, which take the form:
+/// ```beancount
+/// option "title" "Personal Accounts"
+/// ```
+/// Options which aren't set keep their default values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LedgerOptions {
+    title: Option<String>,
+    operating_currencies: Vec<String>,
+    root_names: RootNames,
+    booking_method: BookingMethod,
+    tolerance_defaults: ToleranceDefaults,
+    render_commas: bool,
+}
+
+impl LedgerOptions {
+    /// Returns the title of the ledger, set with the `title` option.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Returns the currencies set with the `operating_currency` option, which can be given more
+    /// than once, in the order they were given.
+    pub fn operating_currencies(&self) -> &[String] {
+        &self.operating_currencies
+    }
+
+    /// Returns the names of the root accounts.
+    pub fn root_names(&self) -> &RootNames {
+        &self.root_names
+    }
+
+    /// Returns the booking method of accounts opened without one, set with the `booking_method`
+    /// option.
+    pub fn booking_method(&self) -> BookingMethod {
+        self.booking_method
+    }
+
+    /// Returns the tolerances used for integer amounts.
+    pub fn tolerance_defaults(&self) -> &ToleranceDefaults {
+        &self.tolerance_defaults
+    }
+
+    /// Returns `true` if numbers should be rendered with commas separating the thousands, set with
+    /// the `render_commas` option.
+    pub fn render_commas(&self) -> bool {
+        self.render_commas
+    }
+
+    /// Sets the option `name` to `value`. Returns an [`OptionError`] if the option isn't known or
+    /// the value isn't valid for the option, in which case the options are unchanged.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        let invalid = || OptionError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+
+        if let Some(result) = self.root_names.set(name, value) {
+            return result;
+        }
+
+        match name {
+            "title" => self.title = Some(value.to_string()),
+            "operating_currency" => self.operating_currencies.push(value.to_string()),
+            "booking_method" => self.booking_method = value.parse().map_err(|_| invalid())?,
+            "inferred_tolerance_default" => {
+                // The value takes the form `CURRENCY:TOLERANCE`, where `*` stands for every
+                // currency
+                let (currency, tolerance) = value
+                    .split_once(':')
+                    .and_then(|(currency, tolerance)| {
+                        let tolerance = tolerance.parse::<Decimal>().ok()?;
+                        (!tolerance.is_sign_negative()).then_some((currency, tolerance))
+                    })
+                    .ok_or_else(invalid)?;
+                self.tolerance_defaults
+                    .set(Some(currency).filter(|c| *c != "*"), tolerance);
+            }
+            "render_commas" => {
+                self.render_commas = match value.to_ascii_lowercase().as_str() {
+                    "true" | "1" => true,
+                    "false" | "0" => false,
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(OptionError::Unknown(name.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+/// The error returned by [`LedgerOptions::set`].
+#[derive(Error, Debug, PartialEq)]
+pub enum OptionError {
+    #[error("unknown option \"{0}\"")]
+    Unknown(String),
+    #[error("invalid value \"{value}\" for the option \"{name}\"")]
+    InvalidValue { name: String, value: String },
+    #[error(
+        "\"{value}\" is already the name of another root account, so it can't be used for \"{name}\""
+    )]
+    DuplicateRootName { name: String, value: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn set_works() {
+        let mut options = LedgerOptions::default();
+        options.set("title", "Personal Accounts").unwrap();
+        options.set("operating_currency", "GBP").unwrap();
+        options.set("operating_currency", "EUR").unwrap();
+        options.set("name_assets", "Actifs").unwrap();
+        options.set("booking_method", "FIFO").unwrap();
+        options.set("inferred_tolerance_default", "JPY:1").unwrap();
+        options
+            .set("inferred_tolerance_default", "*:0.005")
+            .unwrap();
+        options.set("render_commas", "TRUE").unwrap();
+
+        assert_eq!(options.title(), Some("Personal Accounts"));
+        assert_eq!(
+            options.operating_currencies(),
+            ["GBP".to_string(), "EUR".to_string()]
+        );
+        assert_eq!(options.root_names().name(AccountType::Asset), "Actifs");
+        assert_eq!(options.root_names().name(AccountType::Income), "Income");
+        assert_eq!(options.booking_method(), BookingMethod::Fifo);
+        assert_eq!(options.tolerance_defaults().get("JPY"), dec!(1));
+        assert_eq!(options.tolerance_defaults().get("GBP"), dec!(0.005));
+        assert!(options.render_commas());
+
+        let unchanged = options.clone();
+        assert_eq!(
+            options.set("plugin_processing_mode", "raw"),
+            Err(OptionError::Unknown("plugin_processing_mode".to_string()))
+        );
+        for (name, value) in [
+            ("name_assets", "actifs"),
+            ("name_assets", "Actifs:Courants"),
+            ("booking_method", "fifo"),
+            ("inferred_tolerance_default", "GBP"),
+            ("inferred_tolerance_default", "GBP:-1"),
+            ("render_commas", "yes please"),
+        ] {
+            assert_eq!(
+                options.set(name, value),
+                Err(OptionError::InvalidValue {
+                    name: name.to_string(),
+                    value: value.to_string()
+                })
+            );
+        }
+        assert_eq!(
+            options.set("name_income", "Actifs"),
+            Err(OptionError::DuplicateRootName {
+                name: "name_income".to_string(),
+                value: "Actifs".to_string()
+            })
+        );
+        assert_eq!(
+            options.set("name_expenses", "Income"),
+            Err(OptionError::DuplicateRootName {
+                name: "name_expenses".to_string(),
+                value: "Income".to_string()
+            })
+        );
+        assert_eq!(options, unchanged);
+
+        // Setting a root account's own name again is fine
+        options.set("name_assets", "Actifs").unwrap();
+        assert_eq!(options, unchanged);
+    }
+}
@@ -1,327 +1,1125 @@
-use std::fmt::Display;
+use std::{fmt::Display, ops::Range};
+
+use date::Date;
+use rust_decimal::Decimal;
+use thiserror::Error;
 
 use crate::{
-    accounts_doc::{Account, AccountsDocument, ConversionPosting, Posting, RegularPosting},
+    accounts_doc::{
+        Account, AccountsDocument, AddTransactionError, BalanceAssertionError, CloseAccountError,
+        ConversionPosting, CostPosting, OpenAccountError, PadError, Posting, PriceError,
+        RegularPosting,
+    },
+    inventory::CostSpec,
+    options::OptionError,
     tokenizer::{Token, TokenKind, TokenizeError},
-    types::AccountId,
+    types::{AccountId, Amount, BookingMethod, BookingMethodFromStrError},
 };
 
 macro_rules! expect_token {
-    ($tokenizer:expr, $pattern:pat => $binding:expr, $err_msg:expr, $line:ident, $column:ident) => {{
+    ($tokenizer:expr, $pattern:pat => $binding:expr, $expected:expr, $location:expr) => {{
         let Some(token) = $tokenizer.next().transpose()? else {
-            return Err(ParseError {
-                msg: $err_msg.to_string(),
-                line: $line,
-                column: $column,
-            });
+            return Err(ParseError::unexpected_eof($expected, $location));
         };
 
-        let $pattern = token.kind else {
-            return Err(ParseError {
-                msg: $err_msg.to_string(),
-                line: token.line,
-                column: token.column,
-            });
+        let location = Location::from(&token);
+        let binding = match token.kind {
+            $pattern => $binding,
+            found => {
+                return Err(ParseError::new(
+                    ParseErrorKind::ExpectedToken {
+                        expected: $expected,
+                        found,
+                    },
+                    location,
+                ));
+            }
         };
 
-        ($binding, token.line, token.column)
+        (binding, location)
     }};
 }
 
+/// The location of a token in the source, used to position a [`ParseError`].
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Location {
+    line: usize,
+    column: usize,
+    span: Range<usize>,
+}
+
+impl From<&Token> for Location {
+    fn from(token: &Token) -> Self {
+        Location {
+            line: token.line,
+            column: token.column,
+            span: token.span.clone(),
+        }
+    }
+}
+
+/// The reason a [`ParseError`] occurred.
+#[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// The source couldn't be split into tokens.
+    #[error("{0}")]
+    Tokenize(TokenizeError),
+    /// A token was found where a different token was expected. The `expected` field describes
+    /// the token, or tokens, which would have been valid.
+    #[error("expected {expected}, found {found}")]
+    ExpectedToken {
+        expected: &'static str,
+        found: TokenKind,
+    },
+    /// The file ended part way through a directive.
+    #[error("unexpected end of file, expected {expected}")]
+    UnexpectedEof { expected: &'static str },
+    /// An open directive for an account which has already been opened.
+    #[error("account already exists")]
+    DuplicateAccount,
+    /// A transaction which was parsed successfully but isn't valid.
+    #[error("{0}")]
+    TransactionInvalid(AddTransactionError),
+    /// A balance assertion which was parsed successfully but doesn't hold.
+    #[error("{0}")]
+    BalanceAssertionInvalid(BalanceAssertionError),
+    /// A close directive which was parsed successfully but couldn't be applied.
+    #[error("{0}")]
+    CloseInvalid(CloseAccountError),
+    /// A pad directive which was parsed successfully but isn't valid.
+    #[error("{0}")]
+    PadInvalid(PadError),
+    /// A price directive which was parsed successfully but isn't valid.
+    #[error("{0}")]
+    PriceInvalid(PriceError),
+    /// The booking method on an open directive isn't recognised.
+    #[error("{0}")]
+    UnknownBookingMethod(BookingMethodFromStrError),
+    /// An option line which isn't valid. An unknown option is only a warning.
+    #[error("{0}")]
+    OptionInvalid(OptionError),
+    /// A pad directive which isn't followed by a balance assertion for the padded account which
+    /// needs padding.
+    #[error("unused pad directive")]
+    UnusedPad,
+    /// A total price, given with `@@`, on a posting of zero units, so the price of each unit
+    /// can't be derived from it.
+    #[error("a total price needs a non-zero number of units")]
+    TotalPriceOnZeroUnits,
+}
+
+/// The error returned by [`parse`] and [`parse_with_recovery`]. Holds the position of the token
+/// which caused the error. If the error was caused by an unexpected end of file then the position
+/// is that of the last token.
 #[derive(Debug, PartialEq)]
 pub struct ParseError {
-    msg: String,
+    kind: ParseErrorKind,
     line: usize,
     column: usize,
+    span: Range<usize>,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, location: Location) -> Self {
+        ParseError {
+            kind,
+            line: location.line,
+            column: location.column,
+            span: location.span,
+        }
+    }
+
+    /// Creates a [`ParseErrorKind::ExpectedToken`] error positioned at the unexpected `token`.
+    fn expected(expected: &'static str, token: Token) -> Self {
+        let location = Location::from(&token);
+        ParseError::new(
+            ParseErrorKind::ExpectedToken {
+                expected,
+                found: token.kind,
+            },
+            location,
+        )
+    }
+
+    /// Creates a [`ParseErrorKind::UnexpectedEof`] error positioned at `location`, which should be
+    /// the location of the last token.
+    fn unexpected_eof(expected: &'static str, location: Location) -> Self {
+        ParseError::new(ParseErrorKind::UnexpectedEof { expected }, location)
+    }
+
+    /// Returns the reason for the error.
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    /// Returns `true` if the error is only a warning, which doesn't stop the document from being
+    /// used e.g. an unknown option, which is ignored, or an account closed with a non-zero balance,
+    /// which is still closed.
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self.kind,
+            ParseErrorKind::OptionInvalid(OptionError::Unknown(_))
+                | ParseErrorKind::CloseInvalid(CloseAccountError::NonZeroBalance(_))
+        )
+    }
+
+    /// Returns the one-indexed line number of the start of the error.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the one-indexed column of the start of the error.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the byte range of the source which caused the error.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.msg)
+        self.kind.fmt(f)
     }
 }
 
 impl From<TokenizeError> for ParseError {
     fn from(error: TokenizeError) -> Self {
         ParseError {
-            msg: error.msg,
             line: error.line,
             column: error.column,
+            span: error.span.clone(),
+            kind: ParseErrorKind::Tokenize(error),
         }
     }
 }
 
 impl std::error::Error for ParseError {}
 
+/// Parses the tokens into an [`AccountsDocument`], stopping at the first error. Warnings are
+/// ignored.
 pub fn parse(
     tokenizer: impl IntoIterator<Item = Result<Token, TokenizeError>>,
 ) -> Result<AccountsDocument, ParseError> {
-    let mut tokenizer = tokenizer.into_iter();
+    let (accounts_doc, errors) = parse_with_recovery(tokenizer);
+
+    match errors.into_iter().find(|error| !error.is_warning()) {
+        Some(error) => Err(error),
+        None => Ok(accounts_doc),
+    }
+}
+
+/// Parses the tokens into an [`AccountsDocument`], collecting every error rather than stopping at
+/// the first. Every directive is parsed before any are applied to the document, and they're
+/// applied in date order, so a directive can come before the directives it depends on in the
+/// file. On the same date accounts are opened first, then balances are asserted, then the other
+/// directives are applied in the order of the file, and accounts are closed last.
+///
+/// When a directive can't be parsed the error is recorded and the parser skips ahead to the next
+/// line which starts with a date. The returned document contains every directive which was parsed
+/// and applied successfully. The errors found while parsing come first, followed by the errors
+/// found while applying the directives, in date order. The errors include warnings, see
+/// [`ParseError::is_warning`].
+pub fn parse_with_recovery(
+    tokenizer: impl IntoIterator<Item = Result<Token, TokenizeError>>,
+) -> (AccountsDocument, Vec<ParseError>) {
+    let mut tokens = Tokens::new(tokenizer);
     let mut accounts_doc = AccountsDocument::new();
+    let mut errors = vec![];
+    // The locations of the pads in the document, in the same order as `AccountsDocument::pads`
+    let mut pad_locations = vec![];
 
-    //TODO: handle newlines at start of file
-    let line = 0;
-    let column = 0;
-    let (_, _, _) = expect_token!(
-        tokenizer,
-        TokenKind::OptionLine => (),
-        "expected option line",
-        line,
-        column
+    if let Err(error) = parse_option_line(&mut tokens, &mut accounts_doc) {
+        errors.push(error);
+        resync(&mut tokens, &mut errors);
+    }
+
+    let mut directives = vec![];
+    while tokens.peek().is_some() {
+        if let Err(error) = parse_directive(&mut tokens, &mut accounts_doc, &mut directives) {
+            errors.push(error);
+            resync(&mut tokens, &mut errors);
+        }
+    }
+
+    // The directives are applied once they've all been parsed, in date order, so that their order
+    // in the file doesn't matter. The sort is stable so directives of the same type on the same
+    // date are applied in the order of the file.
+    directives.sort_by_key(|directive| (directive.date(), directive.order()));
+    for directive in directives {
+        if let Err(error) = directive.apply(&mut accounts_doc, &mut pad_locations) {
+            errors.push(error);
+        }
+    }
+
+    // Pads are only known to be unused once every balance assertion has been parsed
+    errors.extend(
+        accounts_doc
+            .pads()
+            .iter()
+            .zip(pad_locations)
+            .filter(|(pad, _)| !pad.is_used())
+            .map(|(_, location)| ParseError::new(ParseErrorKind::UnusedPad, location)),
     );
 
-    // transactions or open directive loop
-    'tx_open_loop: while let Some(token) = tokenizer.next().transpose()? {
-        if token.kind == TokenKind::Newline {
-            continue;
+    (accounts_doc, errors)
+}
+
+/// A peekable token stream which keeps track of whether the next token is at the start of a line.
+struct Tokens<I: Iterator<Item = Result<Token, TokenizeError>>> {
+    inner: std::iter::Peekable<std::iter::Fuse<I>>,
+    line_start: bool,
+}
+
+impl<I: Iterator<Item = Result<Token, TokenizeError>>> Tokens<I> {
+    fn new(tokenizer: impl IntoIterator<IntoIter = I>) -> Self {
+        Tokens {
+            inner: tokenizer.into_iter().fuse().peekable(),
+            line_start: true,
         }
+    }
 
-        let TokenKind::Date(date) = token.kind else {
-            return Err(ParseError {
-                msg: "expected date".to_string(),
-                line: token.line,
-                column: token.column,
-            });
-        };
+    fn peek(&mut self) -> Option<&Result<Token, TokenizeError>> {
+        self.inner.peek()
+    }
+}
 
-        match tokenizer.next().transpose()? {
-            Some(Token {
-                kind: TokenKind::DirectiveOpen,
-                line,
-                column,
-            }) => {
-                let (account, line, column) = expect_token!(
-                    tokenizer,
-                    TokenKind::Account(account) => account,
-                    "expected account",
-                    line,
-                    column
-                );
+impl<I: Iterator<Item = Result<Token, TokenizeError>>> Iterator for Tokens<I> {
+    type Item = Result<Token, TokenizeError>;
 
-                let (currency, _, _) = expect_token!(
-                        tokenizer,
-                        TokenKind::Currency(currency) => currency,
-                        "expected amount",
-                        line,
-                        column
-                );
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.inner.next();
+        self.line_start = matches!(
+            token,
+            Some(Ok(Token {
+                kind: TokenKind::Newline,
+                ..
+            }))
+        );
+        token
+    }
+}
+
+/// Skips tokens until the start of the next line that begins with a date or an option i.e. the
+/// start of the next directive. Any tokenize errors found along the way are added to `errors`.
+fn resync<I: Iterator<Item = Result<Token, TokenizeError>>>(
+    tokens: &mut Tokens<I>,
+    errors: &mut Vec<ParseError>,
+) {
+    loop {
+        if tokens.line_start
+            && matches!(
+                tokens.peek(),
+                Some(Ok(Token {
+                    kind: TokenKind::Date(_) | TokenKind::OptionLine { .. },
+                    ..
+                }))
+            )
+        {
+            return;
+        }
+
+        match tokens.next() {
+            Some(Err(error)) => errors.push(error.into()),
+            Some(Ok(_)) => continue,
+            None => return,
+        }
+    }
+}
+
+fn parse_option_line(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    accounts_doc: &mut AccountsDocument,
+) -> Result<(), ParseError> {
+    //TODO: handle newlines at start of file
+    let ((name, value), location) = expect_token!(
+        tokens,
+        TokenKind::OptionLine { name, value } => (name, value),
+        "option line",
+        Location::default()
+    );
+
+    apply_option(accounts_doc, name, value, location)
+}
+
+/// Sets the option `name` to `value` in `accounts_doc`. An unknown option is returned as an error
+/// which is a warning, see [`ParseError::is_warning`].
+fn apply_option(
+    accounts_doc: &mut AccountsDocument,
+    name: String,
+    value: String,
+    location: Location,
+) -> Result<(), ParseError> {
+    accounts_doc
+        .set_option(&name, &value)
+        .map_err(|e| ParseError::new(ParseErrorKind::OptionInvalid(e), location))
+}
+
+/// A directive which has been parsed but not yet applied to an [`AccountsDocument`], along with the
+/// locations used to position the errors found when it's applied.
+enum Directive {
+    Open {
+        date: Date,
+        account: AccountId,
+        currencies: Vec<String>,
+        booking_method: Option<BookingMethod>,
+        account_location: Location,
+    },
+    Balance {
+        date: Date,
+        account: AccountId,
+        amount: Amount,
+        account_location: Location,
+        amount_location: Location,
+    },
+    Close {
+        date: Date,
+        account: AccountId,
+        account_location: Location,
+    },
+    Pad {
+        date: Date,
+        account: AccountId,
+        source_account: AccountId,
+        location: Location,
+        account_location: Location,
+        source_account_location: Location,
+    },
+    Price {
+        date: Date,
+        currency: String,
+        price: Amount,
+        price_location: Location,
+    },
+    Transaction {
+        date: Date,
+        payee: Option<String>,
+        narration: String,
+        postings: Vec<Posting>,
+        header_location: Location,
+        posting_locations: Vec<Location>,
+    },
+}
+
+impl Directive {
+    fn date(&self) -> Date {
+        match self {
+            Directive::Open { date, .. }
+            | Directive::Balance { date, .. }
+            | Directive::Close { date, .. }
+            | Directive::Pad { date, .. }
+            | Directive::Price { date, .. }
+            | Directive::Transaction { date, .. } => *date,
+        }
+    }
+
+    /// Returns the position of the directive among the directives on the same date. As in
+    /// Beancount, accounts are opened and balances are asserted at the start of the day, and
+    /// accounts are closed at the end of the day.
+    fn order(&self) -> u8 {
+        match self {
+            Directive::Open { .. } => 0,
+            Directive::Balance { .. } => 1,
+            Directive::Pad { .. } | Directive::Price { .. } | Directive::Transaction { .. } => 2,
+            Directive::Close { .. } => 3,
+        }
+    }
 
+    /// Applies the directive to `accounts_doc`, returning an error positioned at the part of the
+    /// directive which isn't valid. The location of each pad applied is added to `pad_locations`.
+    fn apply(
+        self,
+        accounts_doc: &mut AccountsDocument,
+        pad_locations: &mut Vec<Location>,
+    ) -> Result<(), ParseError> {
+        match self {
+            Directive::Open {
+                date,
+                account,
+                currencies,
+                booking_method,
+                account_location,
+            } => {
+                let booking_method =
+                    booking_method.unwrap_or(accounts_doc.options().booking_method());
                 accounts_doc
                     .open_an_account(Account {
-                        id: AccountId {
-                            name: account.name,
-                            type_: account.type_,
-                        },
-                        currency,
+                        id: account,
+                        currencies,
                         opening_date: date,
+                        closing_date: None,
+                        booking_method,
                     })
-                    .map_err(|_| ParseError {
-                        msg: "account already exists".to_string(),
-                        line: 0,
-                        column: 0,
+                    .map_err(|e| match e {
+                        OpenAccountError::AccountAlreadyExists => {
+                            ParseError::new(ParseErrorKind::DuplicateAccount, account_location)
+                        }
+                    })
+            }
+            Directive::Balance {
+                date,
+                account,
+                amount,
+                account_location,
+                amount_location,
+            } => accounts_doc
+                .assert_balance(date, account, amount)
+                .map_err(|e| {
+                    // Errors about the account point at the account, otherwise at the amount.
+                    let location = match e {
+                        BalanceAssertionError::AccountNotFound
+                        | BalanceAssertionError::AccountNotOpen => account_location,
+                        BalanceAssertionError::IncorrectCurrency
+                        | BalanceAssertionError::Failed { .. } => amount_location,
+                    };
+                    ParseError::new(ParseErrorKind::BalanceAssertionInvalid(e), location)
+                }),
+            Directive::Close {
+                date,
+                account,
+                account_location,
+            } => accounts_doc
+                .close_account(date, &account)
+                .map_err(|e| ParseError::new(ParseErrorKind::CloseInvalid(e), account_location)),
+            Directive::Pad {
+                date,
+                account,
+                source_account,
+                location,
+                account_location,
+                source_account_location,
+            } => {
+                accounts_doc
+                    .pad_account(date, account, source_account)
+                    .map_err(|e| {
+                        // Errors about the source account point at the source account, otherwise
+                        // at the padded account.
+                        let location = match e {
+                            PadError::SourceAccountNotFound
+                            | PadError::SourceAccountNotOpen
+                            | PadError::SourceAccountClosed
+                            | PadError::NoCommonCurrency => source_account_location,
+                            PadError::AccountNotFound
+                            | PadError::AccountNotOpen
+                            | PadError::AccountClosed => account_location,
+                        };
+                        ParseError::new(ParseErrorKind::PadInvalid(e), location)
                     })?;
+                pad_locations.push(location);
+                Ok(())
+            }
+            Directive::Price {
+                date,
+                currency,
+                price,
+                price_location,
+            } => accounts_doc
+                .add_price(date, currency, price)
+                .map_err(|e| ParseError::new(ParseErrorKind::PriceInvalid(e), price_location)),
+            Directive::Transaction {
+                date,
+                payee,
+                narration,
+                postings,
+                header_location,
+                posting_locations,
+            } => add_transaction(
+                accounts_doc,
+                date,
+                payee,
+                narration,
+                postings,
+                header_location,
+                posting_locations,
+            ),
+        }
+    }
+}
 
-                // The account opening is now complete. We either have a newline or we've reached
-                // the end of the file. Any other token is an error.
+/// Parses a single directive, or a blank line, and adds it to `directives`. Option lines are
+/// applied to `accounts_doc` straight away.
+fn parse_directive(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    accounts_doc: &mut AccountsDocument,
+    directives: &mut Vec<Directive>,
+) -> Result<(), ParseError> {
+    let Some(token) = tokens.next().transpose()? else {
+        return Ok(());
+    };
 
-                if let Some(token) = tokenizer.next().transpose()? {
-                    if token.kind != TokenKind::Newline {
-                        return Err(ParseError {
-                            msg: "expected newline".to_string(),
-                            line: token.line,
-                            column: token.column,
-                        });
-                    }
-                } else {
-                    // We've reached the end of the file. This is OK since we've parsed a complete account
-                    // open directive.
-                    break 'tx_open_loop;
-                };
-            }
-            Some(Token {
+    if token.kind == TokenKind::Newline {
+        return Ok(());
+    }
+
+    // The location of the start of the directive
+    let directive_location = Location::from(&token);
+    let date = match token.kind {
+        TokenKind::Date(date) => date,
+        TokenKind::OptionLine { name, value } => {
+            return apply_option(accounts_doc, name, value, directive_location);
+        }
+        _ => return Err(ParseError::expected("date", token)),
+    };
+
+    let directive = match tokens.next().transpose()? {
+        Some(
+            token @ Token {
+                kind: TokenKind::DirectiveOpen,
+                ..
+            },
+        ) => parse_open(tokens, date, Location::from(&token)),
+        Some(
+            token @ Token {
+                kind: TokenKind::DirectiveBalance,
+                ..
+            },
+        ) => parse_balance(tokens, date, Location::from(&token)),
+        Some(
+            token @ Token {
+                kind: TokenKind::DirectiveClose,
+                ..
+            },
+        ) => parse_close(tokens, date, Location::from(&token)),
+        Some(
+            token @ Token {
+                kind: TokenKind::DirectivePrice,
+                ..
+            },
+        ) => parse_price(tokens, date, Location::from(&token)),
+        Some(
+            token @ Token {
+                kind: TokenKind::DirectivePad,
+                ..
+            },
+        ) => parse_pad(tokens, date, directive_location, Location::from(&token)),
+        Some(
+            token @ Token {
                 kind: TokenKind::DirectivePostTx,
-                line,
-                column,
-            }) => {
-                let (_, line, column) = expect_token!(
-                    tokenizer,
-                    TokenKind::TxDescription => (),
-                    "expected tx description",
-                    line,
-                    column
-                );
+                ..
+            },
+        ) => parse_transaction(tokens, date, directive_location, Location::from(&token)),
+        Some(token) => Err(ParseError::expected("directive", token)),
+        // we've parsed a date up to this point so the end of the file is an error
+        None => Err(ParseError::unexpected_eof("directive", directive_location)),
+    }?;
+
+    directives.push(directive);
+    Ok(())
+}
 
-                let (_, line, column) = expect_token!(
-                    tokenizer,
-                    TokenKind::Newline => (),
-                    "expected newline",
-                    line,
-                    column
+/// Parses the remainder of an open directive, `location` is the location of the `open` keyword.
+fn parse_open(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    date: Date,
+    location: Location,
+) -> Result<Directive, ParseError> {
+    let (account, account_location) = expect_token!(
+        tokens,
+        TokenKind::Account(account) => account,
+        "account",
+        location
+    );
+
+    // The account is followed by an optional comma separated list of currencies, an optional
+    // booking method, and then either a newline or the end of the file.
+    let mut currencies = vec![];
+    let mut booking_method = None;
+    while let Some(token) = tokens.next().transpose()? {
+        let location = Location::from(&token);
+        match token.kind {
+            TokenKind::Newline => break,
+            TokenKind::Currency(currency) if currencies.is_empty() && booking_method.is_none() => {
+                currencies.push(currency)
+            }
+            TokenKind::Comma if !currencies.is_empty() && booking_method.is_none() => {
+                let (currency, _) = expect_token!(
+                    tokens,
+                    TokenKind::Currency(currency) => currency,
+                    "currency",
+                    location
                 );
+                currencies.push(currency);
+            }
+            TokenKind::TxDescription(method) if booking_method.is_none() => {
+                booking_method = Some(method.parse().map_err(|e| {
+                    ParseError::new(ParseErrorKind::UnknownBookingMethod(e), location.clone())
+                })?);
+            }
+            found => {
+                let expected = match (currencies.is_empty(), booking_method.is_some()) {
+                    (_, true) => "newline or end of file",
+                    (true, false) => "currency, booking method, newline or end of file",
+                    (false, false) => "`,`, booking method, newline or end of file",
+                };
+                return Err(ParseError::new(
+                    ParseErrorKind::ExpectedToken { expected, found },
+                    location,
+                ));
+            }
+        }
+    }
 
-                let mut postings = vec![];
+    Ok(Directive::Open {
+        date,
+        account,
+        currencies,
+        booking_method,
+        account_location,
+    })
+}
 
-                'posts_loop: while let Some(token) = tokenizer.next().transpose()? {
-                    if token.kind == TokenKind::Newline {
-                        // We've reached the end of the postings. We'll add the transaction to the
-                        // accounts document after the loop.
-                        break 'posts_loop;
-                    }
-                    let TokenKind::Account(account_id) = token.kind else {
-                        return Err(ParseError {
-                            msg: "expected account".to_string(),
-                            line: token.line,
-                            column: token.column,
-                        });
-                    };
+/// Parses the remainder of a balance assertion, `location` is the location of the `balance`
+/// keyword.
+fn parse_balance(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    date: Date,
+    location: Location,
+) -> Result<Directive, ParseError> {
+    let (account, account_location) = expect_token!(
+        tokens,
+        TokenKind::Account(account) => account,
+        "account",
+        location
+    );
 
-                    match tokenizer.next().transpose()? {
-                        Some(Token {
-                            kind: TokenKind::Newline,
-                            ..
-                        }) => {
-                            // This is an auto-posting we can add it to the postings list
-                            // and move on.
+    let (amount, amount_location) = expect_token!(
+        tokens,
+        TokenKind::Amount(amount) => amount,
+        "amount",
+        account_location.clone()
+    );
 
-                            postings.push(Posting::Auto(account_id));
-                            continue;
-                        }
-                        None => {
-                            // We've reached the end of the file. This is OK since we've parsed a
-                            // complete posting.
+    expect_end_of_line(tokens)?;
 
-                            postings.push(Posting::Auto(account_id));
+    Ok(Directive::Balance {
+        date,
+        account,
+        amount,
+        account_location,
+        amount_location,
+    })
+}
 
-                            accounts_doc
-                                .add_transaction(date, "todo", postings)
-                                .unwrap(); //TODO: tx description + unwrap
-                            break 'tx_open_loop;
-                        }
-                        Some(Token {
-                            kind: TokenKind::Amount(amount),
-                            ..
-                        }) => {
-                            match tokenizer.next().transpose()? {
-                                Some(Token {
-                                    kind: TokenKind::Newline,
-                                    ..
-                                }) => {
-                                    // This posting has no conversion, we can add it to the postings list
-                                    // and move on.
-
-                                    postings.push(Posting::Regular(RegularPosting {
-                                        account_id,
-                                        amount: amount.amount,
-                                        currency: amount.currency,
-                                    }));
-                                    continue;
-                                }
-                                Some(Token {
-                                    kind: TokenKind::At,
-                                    line,
-                                    column,
-                                }) => {
-                                    let (conversion, _, _) = expect_token!(
-                                        tokenizer,
-                                        TokenKind::Amount(conversion) => conversion,
-                                        "expected amount",
-                                        line,
-                                        column
-                                    );
-
-                                    if let Some(token) = tokenizer.next().transpose()? {
-                                        if token.kind != TokenKind::Newline {
-                                            return Err(ParseError {
-                                                msg: "expected newline".to_string(),
-                                                line: token.line,
-                                                column: token.column,
-                                            });
-                                        }
-                                        postings.push(Posting::Conversion(ConversionPosting {
-                                            account_id,
-                                            account_amount: amount.amount,
-                                            account_currency: amount.currency,
-                                            tx_currency: conversion.currency,
-                                            rate: conversion.amount,
-                                        }));
-
-                                        continue;
-                                    } else {
-                                        // We've reached the end of the file. This is OK since we've parsed
-                                        // a complete posting.
-
-                                        postings.push(Posting::Conversion(ConversionPosting {
-                                            account_id,
-                                            account_amount: amount.amount,
-                                            account_currency: amount.currency,
-                                            tx_currency: conversion.currency,
-                                            rate: conversion.amount,
-                                        }));
-
-                                        accounts_doc
-                                            .add_transaction(date, "todo", postings)
-                                            .unwrap(); //TODO: unwrap + tx description
-                                        break 'tx_open_loop;
-                                    };
-                                }
-                                None => {
-                                    // We've reached the end of the file. This is OK since we've parsed a
-                                    // complete posting.
-
-                                    postings.push(Posting::Regular(RegularPosting {
-                                        account_id,
-                                        amount: amount.amount,
-                                        currency: amount.currency,
-                                    }));
-                                    accounts_doc
-                                        .add_transaction(date, "todo", postings)
-                                        .unwrap(); //TODO: tx description + unwrap
-                                    break 'tx_open_loop;
-                                }
-                                _ => {
-                                    return Err(ParseError {
-                                        msg: "expected newline, end of file or @".to_string(),
-                                        line,
-                                        column,
-                                    });
-                                }
-                            }
-                        }
-                        _ => {
-                            return Err(ParseError {
-                                msg: "expected newline, end of file or an amount".to_string(),
-                                line,
-                                column,
-                            });
-                        }
+/// Parses the remainder of a close directive, `location` is the location of the `close` keyword.
+fn parse_close(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    date: Date,
+    location: Location,
+) -> Result<Directive, ParseError> {
+    let (account, account_location) = expect_token!(
+        tokens,
+        TokenKind::Account(account) => account,
+        "account",
+        location
+    );
+
+    expect_end_of_line(tokens)?;
+
+    Ok(Directive::Close {
+        date,
+        account,
+        account_location,
+    })
+}
+
+/// Parses the remainder of a pad directive, `directive_location` is the location of the start of
+/// the directive and `location` is the location of the `pad` keyword.
+fn parse_pad(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    date: Date,
+    directive_location: Location,
+    location: Location,
+) -> Result<Directive, ParseError> {
+    let (account, account_location) = expect_token!(
+        tokens,
+        TokenKind::Account(account) => account,
+        "account",
+        location
+    );
+
+    let (source_account, source_account_location) = expect_token!(
+        tokens,
+        TokenKind::Account(account) => account,
+        "account",
+        account_location.clone()
+    );
+
+    expect_end_of_line(tokens)?;
+
+    Ok(Directive::Pad {
+        date,
+        account,
+        source_account,
+        location: directive_location,
+        account_location,
+        source_account_location,
+    })
+}
+
+/// Parses the remainder of a price directive, `location` is the location of the `price` keyword.
+fn parse_price(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    date: Date,
+    location: Location,
+) -> Result<Directive, ParseError> {
+    let (currency, currency_location) = expect_token!(
+        tokens,
+        TokenKind::Currency(currency) => currency,
+        "currency",
+        location
+    );
+
+    let (price, price_location) = expect_token!(
+        tokens,
+        TokenKind::Amount(amount) => amount,
+        "amount",
+        currency_location
+    );
+
+    expect_end_of_line(tokens)?;
+
+    Ok(Directive::Price {
+        date,
+        currency,
+        price,
+        price_location,
+    })
+}
+
+/// Parses the remainder of a transaction, `location` is the location of the `*` flag.
+fn parse_transaction(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    date: Date,
+    header_location: Location,
+    location: Location,
+) -> Result<Directive, ParseError> {
+    let (description, location) = expect_token!(
+        tokens,
+        TokenKind::TxDescription(description) => description,
+        "tx description",
+        location
+    );
+
+    // A transaction has either a narration or a payee followed by a narration.
+    let (payee, narration) = match tokens.next().transpose()? {
+        Some(Token {
+            kind: TokenKind::Newline,
+            ..
+        }) => (None, description),
+        Some(Token {
+            kind: TokenKind::TxDescription(narration),
+            line,
+            column,
+            span,
+        }) => {
+            let (_, _) = expect_token!(
+                tokens,
+                TokenKind::Newline => (),
+                "newline",
+                Location { line, column, span }
+            );
+            (Some(description), narration)
+        }
+        Some(token) => {
+            return Err(ParseError::expected("tx description or newline", token));
+        }
+        None => {
+            return Err(ParseError::unexpected_eof(
+                "tx description or newline",
+                location,
+            ));
+        }
+    };
+
+    let mut postings = vec![];
+    // The location of each posting, used to position errors in the postings.
+    let mut posting_locations = vec![];
+
+    while let Some(token) = tokens.next().transpose()? {
+        if token.kind == TokenKind::Newline {
+            // A blank line marks the end of the postings.
+            break;
+        }
+        let location = Location::from(&token);
+        let account_id = match token.kind {
+            TokenKind::Account(account_id) => account_id,
+            _ => return Err(ParseError::expected("account", token)),
+        };
+        posting_locations.push(location);
+
+        postings.push(parse_posting(tokens, account_id)?);
+    }
+
+    Ok(Directive::Transaction {
+        date,
+        payee,
+        narration,
+        postings,
+        header_location,
+        posting_locations,
+    })
+}
+
+/// Parses the remainder of a posting to `account_id`, up to and including the newline at the end
+/// of the posting (or the end of the file).
+fn parse_posting(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    account_id: AccountId,
+) -> Result<Posting, ParseError> {
+    let (amount, amount_location) = match tokens.next().transpose()? {
+        // This is an auto-posting
+        None
+        | Some(Token {
+            kind: TokenKind::Newline,
+            ..
+        }) => return Ok(Posting::Auto(account_id)),
+        Some(Token {
+            kind: TokenKind::Amount(amount),
+            line,
+            column,
+            span,
+        }) => (amount, Location { line, column, span }),
+        Some(token) => {
+            return Err(ParseError::expected(
+                "newline, end of file or an amount",
+                token,
+            ));
+        }
+    };
+
+    match tokens.next().transpose()? {
+        // This posting has no conversion
+        None
+        | Some(Token {
+            kind: TokenKind::Newline,
+            ..
+        }) => Ok(Posting::Regular(RegularPosting {
+            account_id,
+            amount: amount.amount,
+            currency: amount.currency,
+        })),
+        Some(
+            token @ Token {
+                kind: TokenKind::At | TokenKind::DoubleAt,
+                ..
+            },
+        ) => {
+            let total = token.kind == TokenKind::DoubleAt;
+            let price = parse_posting_price(tokens, token)?;
+
+            // The rate of a total price is derived from it, but the total is kept so that the
+            // transaction amount is exactly the total.
+            let (rate, total) = if total {
+                let rate = unit_price(price.amount, amount.amount, amount_location)?;
+                (rate, Some(price.amount))
+            } else {
+                (price.amount, None)
+            };
+
+            Ok(Posting::Conversion(ConversionPosting {
+                account_id,
+                account_amount: amount.amount,
+                account_currency: amount.currency,
+                tx_currency: price.currency,
+                rate,
+                total,
+            }))
+        }
+        Some(
+            token @ Token {
+                kind: TokenKind::LeftBrace | TokenKind::DoubleLeftBrace,
+                ..
+            },
+        ) => {
+            let total = token.kind == TokenKind::DoubleLeftBrace;
+            let cost = parse_cost_spec(tokens, total, Location::from(&token))?;
+
+            // A cost can be followed by the price of each unit, or the total price
+            let price = match tokens.next().transpose()? {
+                None
+                | Some(Token {
+                    kind: TokenKind::Newline,
+                    ..
+                }) => None,
+                Some(
+                    token @ Token {
+                        kind: TokenKind::At | TokenKind::DoubleAt,
+                        ..
+                    },
+                ) => {
+                    let total = token.kind == TokenKind::DoubleAt;
+                    let mut price = parse_posting_price(tokens, token)?;
+                    if total {
+                        price.amount = unit_price(price.amount, amount.amount, amount_location)?;
                     }
+                    Some(price)
                 }
-                accounts_doc
-                    .add_transaction(date, "todo", postings)
-                    .unwrap(); //TODO: unwrap + description
+                Some(token) => {
+                    return Err(ParseError::expected("newline, end of file, @ or @@", token));
+                }
+            };
+
+            Ok(Posting::Cost(CostPosting {
+                account_id,
+                units: amount.amount,
+                currency: amount.currency,
+                cost,
+                price,
+                booked: vec![],
+            }))
+        }
+        Some(token) => Err(ParseError::expected(
+            "newline, end of file, @, @@ or a cost",
+            token,
+        )),
+    }
+}
+
+/// Returns the price of each unit for a total price of `total` on a posting of `units`. Returns an
+/// error positioned at `location`, the location of the units, if the price can't be derived
+/// because there are no units.
+fn unit_price(total: Decimal, units: Decimal, location: Location) -> Result<Decimal, ParseError> {
+    total
+        .checked_div(units.abs())
+        .ok_or_else(|| ParseError::new(ParseErrorKind::TotalPriceOnZeroUnits, location))
+}
+
+/// Parses the amount following `at`, which is either `@` or `@@`, up to and including the end of
+/// the line.
+fn parse_posting_price(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    at: Token,
+) -> Result<Amount, ParseError> {
+    let (price, _) = expect_token!(
+        tokens,
+        TokenKind::Amount(price) => price,
+        "amount",
+        Location::from(&at)
+    );
+    expect_end_of_line(tokens)?;
+
+    Ok(price)
+}
+
+/// Parses the remainder of a cost specification, `location` is the location of the opening brace.
+/// A total cost, which is surrounded by `{{` and `}}`, must contain an amount.
+fn parse_cost_spec(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+    total: bool,
+    location: Location,
+) -> Result<CostSpec, ParseError> {
+    let close = if total {
+        TokenKind::DoubleRightBrace
+    } else {
+        TokenKind::RightBrace
+    };
+
+    // The cost is an optional amount followed by an optional date, which is preceded by a comma
+    // if there's an amount.
+    let mut spec = CostSpec::default();
+    let mut expected = "amount, date or closing brace";
+    let mut location = location;
+    loop {
+        let Some(token) = tokens.next().transpose()? else {
+            return Err(ParseError::unexpected_eof(expected, location));
+        };
+
+        if token.kind == close {
+            if total && spec.total.is_none() {
+                return Err(ParseError::expected("amount", token));
             }
-            _ => {
-                // `None` (end of file) or any other token (open or create transaction are covered by the match
-                // branches above) is an error
-                // (this is because we've parsed a date up to this point).
-                return Err(ParseError {
-                    msg: "expected either open or post transaction directive".to_string(),
-                    line,
-                    column,
-                });
+            return Ok(spec);
+        }
+
+        location = Location::from(&token);
+        match token.kind {
+            TokenKind::Amount(amount) if spec.currency.is_none() && spec.date.is_none() => {
+                if total {
+                    spec.total = Some(amount.amount);
+                } else {
+                    spec.per_unit = Some(amount.amount);
+                }
+                spec.currency = Some(amount.currency);
+                expected = "`,` or closing brace";
+            }
+            TokenKind::Comma if spec.currency.is_some() && spec.date.is_none() => {
+                let (date, date_location) = expect_token!(
+                    tokens,
+                    TokenKind::Date(date) => date,
+                    "date",
+                    location
+                );
+                spec.date = Some(date);
+                location = date_location;
+                expected = "closing brace";
+            }
+            TokenKind::Date(date) if spec.currency.is_none() && spec.date.is_none() => {
+                spec.date = Some(date);
+                expected = "closing brace";
+            }
+            found => {
+                return Err(ParseError::new(
+                    ParseErrorKind::ExpectedToken { expected, found },
+                    location,
+                ));
             }
         }
     }
+}
 
-    Ok(accounts_doc)
+/// Consumes the end of a line, which is either a newline or the end of the file. Any other token
+/// is an error.
+fn expect_end_of_line(
+    tokens: &mut impl Iterator<Item = Result<Token, TokenizeError>>,
+) -> Result<(), ParseError> {
+    match tokens.next().transpose()? {
+        None
+        | Some(Token {
+            kind: TokenKind::Newline,
+            ..
+        }) => Ok(()),
+        Some(token) => Err(ParseError::expected("newline", token)),
+    }
+}
+
+/// Adds a transaction to `accounts_doc`. If the transaction is invalid the
+/// [`AddTransactionError`] is converted to a
+/// [`ParseError`] positioned at the posting which caused the error, or at the transaction header
+/// if the error applies to the whole transaction. `posting_locations` holds the location of each
+/// posting in `postings`.
+fn add_transaction(
+    accounts_doc: &mut AccountsDocument,
+    date: Date,
+    payee: Option<String>,
+    narration: String,
+    postings: Vec<Posting>,
+    header_location: Location,
+    mut posting_locations: Vec<Location>,
+) -> Result<(), ParseError> {
+    accounts_doc
+        .try_add_transaction(date, payee, narration, postings)
+        .map_err(|(error, index)| {
+            let location = index
+                .filter(|&i| i < posting_locations.len())
+                .map(|i| posting_locations.swap_remove(i))
+                .unwrap_or(header_location);
+            ParseError::new(ParseErrorKind::TransactionInvalid(error), location)
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use date::{Date, date};
-    use rust_decimal::Decimal;
+    use rust_decimal::{Decimal, dec};
 
-    use crate::{types::AccountType, types::Amount};
+    use crate::{
+        inventory::{BookingError, Inventory},
+        tokenizer::Tokenizer,
+        types::{AccountType, Amount, BookingMethod},
+    };
 
     use super::*;
 
@@ -329,14 +1127,19 @@ mod tests {
     fn happy_path() {
         let mut tokens = vec![];
         tokens.push(Ok(Token {
-            kind: TokenKind::OptionLine,
+            kind: TokenKind::OptionLine {
+                name: "operating_currency".to_string(),
+                value: "GBP".to_string(),
+            },
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         tokens.push(Ok(Token {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_open_account_tokens(
             &mut tokens,
@@ -349,6 +1152,7 @@ mod tests {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_open_account_tokens(
             &mut tokens,
@@ -361,6 +1165,7 @@ mod tests {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_open_account_tokens(
             &mut tokens,
@@ -373,17 +1178,20 @@ mod tests {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         tokens.push(Ok(Token {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_tx_declaration_tokens(&mut tokens, date! {1912-01-12});
         tokens.push(Ok(Token {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_post_tokens(
             &mut tokens,
@@ -396,6 +1204,7 @@ mod tests {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_post_tokens(
             &mut tokens,
@@ -408,6 +1217,7 @@ mod tests {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_conversion_post_tokens(
             &mut tokens,
@@ -422,6 +1232,7 @@ mod tests {
             kind: TokenKind::Newline,
             line: 0,
             column: 0,
+            span: 0..0,
         }));
         add_auto_posting_tokens(&mut tokens, AccountType::Asset, "account name");
 
@@ -432,27 +1243,33 @@ mod tests {
             vec![
                 Account {
                     id: AccountId {
-                        name: "account name".to_string(),
+                        components: vec!["account name".to_string()],
                         type_: crate::types::AccountType::Asset
                     },
-                    currency: "GBP".to_string(),
-                    opening_date: date! {1912-01-12}
+                    currencies: vec!["GBP".to_string()],
+                    opening_date: date! {1912-01-12},
+                    closing_date: None,
+                    booking_method: BookingMethod::Strict,
                 },
                 Account {
                     id: AccountId {
-                        name: "another account".to_string(),
+                        components: vec!["another account".to_string()],
                         type_: crate::types::AccountType::Asset
                     },
-                    currency: "GBP".to_string(),
-                    opening_date: date! {1912-01-12}
+                    currencies: vec!["GBP".to_string()],
+                    opening_date: date! {1912-01-12},
+                    closing_date: None,
+                    booking_method: BookingMethod::Strict,
                 },
                 Account {
                     id: AccountId {
-                        name: "yet another account".to_string(),
+                        components: vec!["yet another account".to_string()],
                         type_: crate::types::AccountType::Asset
                     },
-                    currency: "EUR".to_string(),
-                    opening_date: date! {1912-01-12}
+                    currencies: vec!["EUR".to_string()],
+                    opening_date: date! {1912-01-12},
+                    closing_date: None,
+                    booking_method: BookingMethod::Strict,
                 }
             ]
         );
@@ -463,13 +1280,13 @@ mod tests {
             balances.next(),
             Some((
                 &AccountId {
-                    name: "account name".to_string(),
+                    components: vec!["account name".to_string()],
                     type_: AccountType::Asset,
                 },
-                Amount {
+                Inventory::from_iter([Amount {
                     amount: "-4.45".parse().expect("hard coded value will parse"),
                     currency: "GBP".to_string(),
-                }
+                }])
             ))
         );
 
@@ -477,13 +1294,13 @@ mod tests {
             balances.next(),
             Some((
                 &AccountId {
-                    name: "another account".to_string(),
+                    components: vec!["another account".to_string()],
                     type_: AccountType::Asset,
                 },
-                Amount {
+                Inventory::from_iter([Amount {
                     amount: "7.45".parse().expect("hard coded value will parse"),
                     currency: "GBP".to_string(),
-                }
+                }])
             ))
         );
 
@@ -491,19 +1308,917 @@ mod tests {
             balances.next(),
             Some((
                 &AccountId {
-                    name: "yet another account".to_string(),
+                    components: vec!["yet another account".to_string()],
                     type_: AccountType::Asset,
                 },
-                Amount {
+                Inventory::from_iter([Amount {
                     amount: "-1.5".parse().expect("hard coded value will parse"),
                     currency: "EUR".to_string(),
-                }
+                }])
             ))
         );
 
         assert_eq!(balances.next(), None);
     }
 
+    #[test]
+    fn payee_and_narration() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:Checking GBP
+2023-02-01 open Expenses:Coffee GBP
+
+2023-02-03 * "Café Nerö" "Flat white \"to go\""
+  Expenses:Coffee  3.20 GBP
+  Assets:Checking
+
+2023-02-04 * "Just a narration"
+  Expenses:Coffee  3.20 GBP
+  Assets:Checking
+"#;
+
+        let accts = parse(Tokenizer::new(raw)).expect("the test ledger is valid");
+        let transactions = accts.transactions();
+
+        assert_eq!(transactions[0].payee(), Some("Café Nerö"));
+        assert_eq!(transactions[0].narration(), r#"Flat white "to go""#);
+        assert_eq!(transactions[1].payee(), None);
+        assert_eq!(transactions[1].narration(), "Just a narration");
+    }
+
+    #[test]
+    fn add_transaction_errors_are_positioned() {
+        let header = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:Checking GBP
+2023-02-01 open Expenses:Coffee GBP
+"#;
+
+        // errors caused by a posting point at the posting
+        let err = parse(Tokenizer::new(format!(
+            "{header}2023-02-03 * \"Coffee\"\n  Expenses:Coffee  3.20 GBP\n  Expenses:Tea\n"
+        )))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                kind: ParseErrorKind::TransactionInvalid(AddTransactionError::AccountNotFound),
+                line: 6,
+                column: 3,
+                span: 158..170,
+            }
+        );
+
+        // errors which apply to the whole transaction point at the transaction header
+        let err = parse(Tokenizer::new(format!(
+            "{header}\n2023-02-03 * \"Coffee\"\n  Expenses:Coffee  3.20 GBP\n  Assets:Checking  -3.00 GBP"
+        )))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                kind: ParseErrorKind::TransactionInvalid(AddTransactionError::NotBalanced {
+                    residual: dec!(0.20),
+                    currency: "GBP".to_string(),
+                    tolerance: dec!(0.005),
+                }),
+                line: 5,
+                column: 1,
+                span: 107..117,
+            }
+        );
+
+        let err = parse(Tokenizer::new(format!(
+            "{header}2023-02-01 open Expenses:Coffee GBP\n"
+        )))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                kind: ParseErrorKind::DuplicateAccount,
+                line: 4,
+                column: 17,
+                span: 122..137,
+            }
+        );
+    }
+
+    #[test]
+    fn recovery_collects_all_errors() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:Checking GBP
+2023-02-01 open Expenses:Coffee GBP,
+2023-02-01 open Expenses:Tea GBP
+
+2023-02-03 * "Coffee"
+  Expenses:Coffee  3.20 GBP
+  Assets:Checking  $$$
+2023-02-04 * "Tea"
+  Expenses:Tea  2.10 GBP
+  Assets:Checking
+
+2023-02-05 * "Unbalanced"
+  Expenses:Tea  2.10 GBP
+  Assets:Checking  -2.00 GBP
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors,
+            vec![
+                ParseError {
+                    kind: ParseErrorKind::ExpectedToken {
+                        expected: "currency",
+                        found: TokenKind::Newline,
+                    },
+                    line: 3,
+                    column: 37,
+                    span: 106..107,
+                },
+                ParseError {
+                    kind: ParseErrorKind::Tokenize(TokenizeError {
+                        msg: "unexpected character sequence".to_string(),
+                        line: 8,
+                        column: 20,
+                        span: 210..213,
+                    }),
+                    line: 8,
+                    column: 20,
+                    span: 210..213,
+                },
+                ParseError {
+                    kind: ParseErrorKind::TransactionInvalid(AddTransactionError::NotBalanced {
+                        residual: dec!(0.10),
+                        currency: "GBP".to_string(),
+                        tolerance: dec!(0.005),
+                    }),
+                    line: 13,
+                    column: 1,
+                    span: 277..287,
+                },
+            ]
+        );
+
+        // The valid directives are kept
+        assert_eq!(accts.accounts.len(), 2);
+        assert_eq!(accts.transactions().len(), 1);
+        assert_eq!(accts.transactions()[0].narration(), "Tea");
+
+        // parse stops at the first error
+        assert_eq!(
+            parse(Tokenizer::new(raw)).unwrap_err(),
+            ParseError {
+                kind: ParseErrorKind::ExpectedToken {
+                    expected: "currency",
+                    found: TokenKind::Newline,
+                },
+                line: 3,
+                column: 37,
+                span: 106..107,
+            }
+        );
+    }
+
+    #[test]
+    fn error_kinds() {
+        let err = parse(Tokenizer::new(
+            "option \"title\" \"x\"\n2023-02-01 open Assets:Checking Assets:Cash\n",
+        ))
+        .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ParseErrorKind::ExpectedToken {
+                expected: "currency, booking method, newline or end of file",
+                found: TokenKind::Account(_),
+            }
+        ));
+        assert_eq!((err.line(), err.column(), err.span()), (2, 33, 51..62));
+        assert_eq!(
+            err.to_string(),
+            "expected currency, booking method, newline or end of file, found account"
+        );
+
+        let err = parse(Tokenizer::new("option \"title\" \"x\"\n2023-02-01")).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &ParseErrorKind::UnexpectedEof {
+                expected: "directive"
+            }
+        );
+        assert_eq!((err.line(), err.column(), err.span()), (2, 1, 19..29));
+    }
+
+    #[test]
+    fn balance_assertions() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:Checking GBP
+2023-02-01 open Expenses:Coffee GBP
+2023-02-01 balance Assets:Checking 0 GBP
+
+2023-02-03 * "Coffee"
+  Expenses:Coffee  3.20 GBP
+  Assets:Checking
+
+2023-02-03 balance Assets:Checking 0.00 GBP
+2023-02-04 balance Assets:Checking -3.2 GBP
+2023-02-04 balance Assets:Checking -3.00 GBP
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors,
+            vec![ParseError {
+                kind: ParseErrorKind::BalanceAssertionInvalid(BalanceAssertionError::Failed {
+                    currency: "GBP".to_string(),
+                    expected: "-3.00".parse().expect("hard coded value will parse"),
+                    actual: "-3.20".parse().expect("hard coded value will parse"),
+                    difference: "-0.20".parse().expect("hard coded value will parse"),
+                }),
+                line: 12,
+                column: 36,
+                span: 340..349,
+            }]
+        );
+        assert_eq!(accts.balance_assertions().len(), 3);
+    }
+
+    #[test]
+    fn pad_directives() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:Checking GBP
+2023-02-01 open Equity:Opening-Balances GBP
+2023-02-01 pad Assets:Checking Equity:Opening-Balances
+
+2023-02-03 balance Assets:Checking 1234.56 GBP
+2023-02-03 pad Assets:Checking Equity:Opening-Balances
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors,
+            vec![ParseError {
+                kind: ParseErrorKind::UnusedPad,
+                line: 7,
+                column: 1,
+                span: 217..227,
+            }]
+        );
+        let padding = &accts.transactions()[0];
+        assert!(padding.is_synthetic());
+        assert_eq!(
+            padding.balance(&AccountId {
+                components: vec!["Checking".to_string()],
+                type_: AccountType::Asset,
+            }),
+            Some(Inventory::from_iter([Amount {
+                amount: "1234.56".parse().expect("hard coded value will parse"),
+                currency: "GBP".to_string(),
+            }]))
+        );
+    }
+
+    #[test]
+    fn pad_each_currency() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:Bank GBP,EUR
+2023-02-01 open Assets:Cash GBP
+2023-02-01 open Equity:Opening-Balances GBP,EUR
+2023-02-01 pad Assets:Bank Equity:Opening-Balances
+2023-02-01 pad Assets:Cash Equity:Opening-Balances
+
+2023-02-03 balance Assets:Bank 100 GBP
+2023-02-03 balance Assets:Bank 50 EUR
+2023-02-03 balance Assets:Cash 0 GBP
+2023-02-04 balance Assets:Bank 0 GBP
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        // Each currency of the bank is padded once, and the cash already balanced so its pad
+        // wasn't used
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    &ParseErrorKind::BalanceAssertionInvalid(BalanceAssertionError::Failed {
+                        currency: "GBP".to_string(),
+                        expected: Decimal::ZERO,
+                        actual: 100.into(),
+                        difference: 100.into(),
+                    }),
+                    11
+                ),
+                (&ParseErrorKind::UnusedPad, 6),
+            ]
+        );
+        let bank = AccountId {
+            components: vec!["Bank".to_string()],
+            type_: AccountType::Asset,
+        };
+        assert_eq!(
+            accts.balance(&bank).map(|b| b.to_string()),
+            Some("50 EUR, 100 GBP".to_string())
+        );
+        assert_eq!(accts.transactions().len(), 2);
+    }
+
+    #[test]
+    fn multi_commodity_accounts() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:Broker GBP,VWRL
+2023-02-01 open Assets:Anything
+2023-02-01 open Equity:Opening-Balances GBP, VWRL
+
+2023-02-03 * "Transfer in"
+  Assets:Broker  10 VWRL
+  Equity:Opening-Balances
+
+2023-02-03 * "Deposit"
+  Assets:Broker  1000.00 GBP
+  Assets:Anything  -1000.00 GBP
+
+2023-02-04 * "Wrong commodity"
+  Assets:Broker  10 EUR
+  Assets:Anything
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line()))
+                .collect::<Vec<_>>(),
+            vec![(
+                &ParseErrorKind::TransactionInvalid(AddTransactionError::IncorrectAccountCurrency),
+                15
+            )]
+        );
+        assert_eq!(
+            accts.accounts[2].currencies,
+            vec!["GBP".to_string(), "VWRL".to_string()]
+        );
+        assert_eq!(
+            accts
+                .balance(&AccountId {
+                    components: vec!["Broker".to_string()],
+                    type_: AccountType::Asset,
+                })
+                .expect("the account exists")
+                .to_string(),
+            "1000.00 GBP, 10 VWRL"
+        );
+    }
+
+    #[test]
+    fn ledger_options() {
+        let raw = r#"option "title" "Personal Accounts"
+option "operating_currency" "GBP"
+option "operating_currency" "EUR"
+option "booking_method" "FIFO"
+option "plugin_processing_mode" "raw"
+2023-02-01 open Assets:Broker
+2023-02-01 open Assets:Isa VWRL "LIFO"
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line(), e.is_warning()))
+                .collect::<Vec<_>>(),
+            vec![(
+                &ParseErrorKind::OptionInvalid(OptionError::Unknown(
+                    "plugin_processing_mode".to_string()
+                )),
+                5,
+                true
+            )]
+        );
+        assert_eq!(accts.options().title(), Some("Personal Accounts"));
+        assert_eq!(
+            accts.options().operating_currencies(),
+            ["GBP".to_string(), "EUR".to_string()]
+        );
+        assert_eq!(
+            accts
+                .accounts
+                .iter()
+                .map(|a| a.booking_method)
+                .collect::<Vec<_>>(),
+            vec![BookingMethod::Fifo, BookingMethod::Lifo]
+        );
+
+        // Warnings don't stop the document from being parsed
+        assert!(parse(Tokenizer::new(raw)).is_ok());
+    }
+
+    #[test]
+    fn root_names() {
+        let raw = r#"option "name_assets" "Actifs"
+option "name_equity" "Capitaux-Propres"
+option "name_income" "revenus"
+2023-02-01 open Actifs:Banque EUR
+2023-02-01 open Capitaux-Propres:Ouverture EUR
+
+2023-02-03 * "Solde d'ouverture"
+  Actifs:Banque  100.00 EUR
+  Capitaux-Propres:Ouverture
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors.iter().map(|e| e.kind()).collect::<Vec<_>>(),
+            vec![&ParseErrorKind::OptionInvalid(OptionError::InvalidValue {
+                name: "name_income".to_string(),
+                value: "revenus".to_string()
+            })]
+        );
+        assert_eq!(
+            accts.options().root_names().name(AccountType::Asset),
+            "Actifs"
+        );
+        assert_eq!(
+            accts
+                .balance(&AccountId {
+                    components: vec!["Banque".to_string()],
+                    type_: AccountType::Asset,
+                })
+                .expect("the account exists")
+                .to_string(),
+            "100.00 EUR"
+        );
+    }
+
+    #[test]
+    fn inferred_tolerances() {
+        let raw = r#"option "inferred_tolerance_default" "JPY:1"
+option "inferred_tolerance_default" "*:0.01"
+option "inferred_tolerance_default" "GBP"
+2023-02-01 open Assets:Bank
+2023-02-01 open Expenses:Food
+
+2023-02-03 * "Within half of the last decimal place"
+  Expenses:Food  3.204 GBP
+  Assets:Bank  -3.20 GBP
+
+2023-02-03 * "Outside half of the last decimal place"
+  Expenses:Food  3.2 GBP
+  Assets:Bank  -3.1 GBP
+
+2023-02-03 * "Integers use the default for the currency"
+  Expenses:Food  1001 JPY
+  Assets:Bank  -1000 JPY
+
+2023-02-03 * "An inferred tolerance takes precedence over the default"
+  Expenses:Food  10 EUR
+  Assets:Bank  -9.99 EUR
+
+2023-02-03 * "Integers use the default for every currency"
+  Expenses:Food  10 USD
+  Assets:Bank  -9 USD
+"#;
+
+        let (_, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    &ParseErrorKind::OptionInvalid(OptionError::InvalidValue {
+                        name: "inferred_tolerance_default".to_string(),
+                        value: "GBP".to_string()
+                    }),
+                    3
+                ),
+                (
+                    &ParseErrorKind::TransactionInvalid(AddTransactionError::NotBalanced {
+                        residual: dec!(0.1),
+                        currency: "GBP".to_string(),
+                        tolerance: dec!(0.05),
+                    }),
+                    11
+                ),
+                (
+                    &ParseErrorKind::TransactionInvalid(AddTransactionError::NotBalanced {
+                        residual: dec!(0.01),
+                        currency: "EUR".to_string(),
+                        tolerance: dec!(0.005),
+                    }),
+                    19
+                ),
+                (
+                    &ParseErrorKind::TransactionInvalid(AddTransactionError::NotBalanced {
+                        residual: dec!(1),
+                        currency: "USD".to_string(),
+                        tolerance: dec!(0.01),
+                    }),
+                    23
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn total_prices() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:BankEUR EUR
+2023-02-01 open Assets:Bank GBP
+2023-02-01 open Assets:Broker GBP,VWRL "FIFO"
+2023-02-01 open Income:Gains GBP
+
+2023-02-03 * "Exchange"
+  Assets:BankEUR  3 EUR @@ 10.00 GBP
+  Assets:Bank
+
+2023-02-04 * "Exchange back"
+  Assets:BankEUR  -3 EUR @@ 9.00 GBP
+  Assets:Bank  9.00 GBP
+
+2023-02-05 * "Buy"
+  Assets:Broker  10 VWRL {85.00 GBP}
+  Assets:Bank  -850.00 GBP
+
+2023-02-06 * "Sell"
+  Assets:Broker  -10 VWRL {} @@ 900.00 GBP
+  Assets:Bank  900.00 GBP
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+        assert_eq!(errors, vec![]);
+
+        let Posting::Conversion(posting) = &accts.transactions()[0].postings()[0] else {
+            panic!("expected a conversion posting");
+        };
+        assert_eq!(posting.total(), Some(dec!(10.00)));
+        assert_eq!(posting.rate(), dec!(10.00) / dec!(3));
+
+        // The auto-posting is exactly the total, rather than the amount multiplied by the rate
+        let bank = AccountId {
+            components: vec!["Bank".to_string()],
+            type_: AccountType::Asset,
+        };
+        assert_eq!(
+            accts.transactions()[0]
+                .balance(&bank)
+                .expect("the account is posted to")
+                .to_string(),
+            "-10.00 GBP"
+        );
+
+        assert_eq!(
+            accts.transactions()[3]
+                .realized_gain()
+                .map(|g| g.amount.to_string()),
+            Some("50.00".to_string())
+        );
+    }
+
+    #[test]
+    fn total_price_on_zero_units() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Assets:BankEUR EUR
+2023-02-01 open Assets:Broker VWRL
+2023-02-01 open Assets:Bank GBP
+
+2023-02-03 * "Exchange"
+  Assets:BankEUR  0 EUR @@ 85.13 GBP
+  Assets:Bank
+
+2023-02-04 * "Buy"
+  Assets:Broker  0 VWRL {85.00 GBP} @@ 85.13 GBP
+  Assets:Bank
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line(), e.column()))
+                .collect::<Vec<_>>(),
+            vec![
+                (&ParseErrorKind::TotalPriceOnZeroUnits, 7, 19),
+                (&ParseErrorKind::TotalPriceOnZeroUnits, 11, 18),
+            ]
+        );
+        assert!(accts.transactions().is_empty());
+    }
+
+    #[test]
+    fn total_cost_on_zero_units() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-01-01 open Assets:Broker VWRL
+2023-01-01 open Assets:Cash GBP
+
+2023-02-01 * "Buy"
+  Assets:Broker  10 VWRL {85.20 GBP}
+  Assets:Cash
+
+2023-02-02 * "Buy"
+  Assets:Broker  0 VWRL {{852 GBP}}
+  Assets:Cash
+
+2023-03-01 * "Sell"
+  Assets:Broker  -0 VWRL {{5 GBP}}
+  Assets:Cash
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    &ParseErrorKind::TransactionInvalid(AddTransactionError::Booking(
+                        BookingError::TotalCostOnZeroUnits
+                    )),
+                    10
+                ),
+                (
+                    &ParseErrorKind::TransactionInvalid(AddTransactionError::Booking(
+                        BookingError::TotalCostOnZeroUnits
+                    )),
+                    14
+                ),
+            ]
+        );
+        assert_eq!(accts.transactions().len(), 1);
+    }
+
+    #[test]
+    fn lots_and_booking() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-01-01 open Assets:Broker GBP,VWRL "FIFO"
+2023-01-01 open Assets:Strict VWRL
+2023-01-01 open Assets:Cash GBP
+
+2023-02-01 * "Buy"
+  Assets:Broker  10 VWRL {85.20 GBP, 2023-01-15}
+  Assets:Cash
+
+2023-02-02 * "Buy"
+  Assets:Broker  10 VWRL {{900 GBP}}
+  Assets:Strict  10 VWRL {80 GBP}
+  Assets:Strict  10 VWRL {81 GBP}
+  Assets:Cash
+
+2023-03-01 * "Sell"
+  Assets:Broker  -15 VWRL {}
+  Assets:Cash  1302 GBP
+
+2023-03-01 * "Sell"
+  Assets:Strict  -5 VWRL {}
+  Assets:Cash
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line()))
+                .collect::<Vec<_>>(),
+            vec![(
+                &ParseErrorKind::TransactionInvalid(AddTransactionError::Booking(
+                    BookingError::AmbiguousLot
+                )),
+                21
+            )]
+        );
+        assert_eq!(accts.accounts[0].booking_method, BookingMethod::Fifo);
+
+        // The oldest lot is reduced first, so the booked cost of the sale is 1302 GBP (10 x 85.20
+        // + 5 x 90).
+        let broker = AccountId {
+            components: vec!["Broker".to_string()],
+            type_: AccountType::Asset,
+        };
+        assert_eq!(
+            accts
+                .balance(&broker)
+                .expect("the account exists")
+                .to_string(),
+            "5 VWRL {90 GBP, 2023-02-02}"
+        );
+        assert_eq!(accts.transactions().len(), 3);
+
+        let err = parse(Tokenizer::new(
+            "option \"title\" \"x\"\n2023-01-01 open Assets:Broker \"HIFO\"\n",
+        ))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "unrecognized booking method HIFO");
+        assert_eq!((err.line(), err.column()), (2, 31));
+    }
+
+    #[test]
+    fn realized_gains() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-01-01 open Assets:Broker GBP,VWRL "FIFO"
+2023-01-01 open Assets:Cash GBP
+2023-01-01 open Income:Gains GBP
+
+2023-02-01 * "Buy"
+  Assets:Broker  20 VWRL {85.20 GBP}
+  Assets:Cash
+
+2023-03-01 * "Sell, the gain is inferred"
+  Assets:Broker  -10 VWRL {} @ 90.00 GBP
+  Assets:Cash  900.00 GBP
+
+2023-03-02 * "Sell, the gain is checked and has the wrong sign"
+  Assets:Broker  -5 VWRL {} @ 80.00 GBP
+  Assets:Cash  400.00 GBP
+  Income:Gains  -26.00 GBP
+
+2023-03-03 * "Sell, the gain is an auto-posting"
+  Assets:Broker  -5 VWRL {} @ 80.00 GBP
+  Assets:Cash  400.00 GBP
+  Income:Gains
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line()))
+                .collect::<Vec<_>>(),
+            vec![(
+                &ParseErrorKind::TransactionInvalid(AddTransactionError::IncorrectGain),
+                17
+            )]
+        );
+
+        let gains = accts
+            .transactions()
+            .iter()
+            .map(|t| t.realized_gain().map(|g| g.amount.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            gains,
+            vec![None, Some("48.00".to_string()), Some("-26.00".to_string())]
+        );
+
+        // The inferred gain is added as the last posting
+        assert_eq!(accts.transactions()[1].postings().len(), 3);
+        let income_gains = AccountId {
+            components: vec!["Gains".to_string()],
+            type_: AccountType::Income,
+        };
+        assert_eq!(
+            accts
+                .balance(&income_gains)
+                .expect("the account exists")
+                .to_string(),
+            "-22.00 GBP"
+        );
+    }
+
+    #[test]
+    fn price_directives() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 price EUR 0.85 GBP
+2023-02-03 price EUR 0.86 GBP
+2023-02-03 price EUR 1 EUR
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors,
+            vec![ParseError {
+                kind: ParseErrorKind::PriceInvalid(PriceError::SameCurrency),
+                line: 4,
+                column: 22,
+                span: 115..120,
+            }]
+        );
+        assert_eq!(
+            accts.prices().latest_rate("EUR", "GBP", date! {2023-02-02}),
+            Some((
+                date! {2023-02-01},
+                "0.85".parse().expect("hard coded value will parse")
+            ))
+        );
+    }
+
+    #[test]
+    fn close_directives() {
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-01 open Liabilities:CreditCard GBP
+2023-02-01 open Expenses:Coffee GBP
+
+2023-02-03 * "Coffee"
+  Expenses:Coffee  3.20 GBP
+  Liabilities:CreditCard
+
+2023-02-04 close Liabilities:CreditCard
+
+2023-02-05 * "Refund"
+  Expenses:Coffee  -3.20 GBP
+  Liabilities:CreditCard
+
+2023-02-06 * "Coffee"
+  Expenses:Coffee  3.20 GBP
+  Liabilities:CreditCard
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind(), e.line(), e.is_warning()))
+                .collect::<Vec<_>>(),
+            vec![
+                // The close is still applied, so the postings after it are rejected
+                (
+                    &ParseErrorKind::CloseInvalid(CloseAccountError::NonZeroBalance(
+                        Inventory::from_iter([Amount {
+                            amount: "-3.20".parse().expect("hard coded value will parse"),
+                            currency: "GBP".to_string(),
+                        }])
+                    )),
+                    9,
+                    true
+                ),
+                (
+                    &ParseErrorKind::TransactionInvalid(AddTransactionError::AccountClosed),
+                    13,
+                    false
+                ),
+                (
+                    &ParseErrorKind::TransactionInvalid(AddTransactionError::AccountClosed),
+                    17,
+                    false
+                ),
+            ]
+        );
+        assert_eq!(
+            accts
+                .balances()
+                .map(|(id, _)| id.name())
+                .collect::<Vec<_>>(),
+            vec!["Coffee"]
+        );
+    }
+
+    #[test]
+    fn directive_order() {
+        // On each date accounts are opened, then balances asserted, then transactions added, then
+        // accounts closed, whatever the order of the file.
+        let raw = r#"option "operating_currency" "GBP"
+2023-02-04 * "Refund"
+  Expenses:Coffee  -3.20 GBP
+  Liabilities:CreditCard
+
+2023-02-04 close Liabilities:CreditCard
+
+2023-02-03 * "Coffee"
+  Expenses:Coffee  3.20 GBP
+  Liabilities:CreditCard
+
+2023-02-03 balance Liabilities:CreditCard  0 GBP
+2023-02-04 balance Liabilities:CreditCard  -3.20 GBP
+
+2023-02-01 open Liabilities:CreditCard GBP
+2023-02-01 open Expenses:Coffee GBP
+"#;
+
+        let (accts, errors) = parse_with_recovery(Tokenizer::new(raw));
+
+        assert_eq!(errors, vec![]);
+        assert_eq!(
+            accts
+                .transactions()
+                .iter()
+                .map(|t| t.narration())
+                .collect::<Vec<_>>(),
+            vec!["Coffee", "Refund"]
+        );
+        assert_eq!(accts.balance_assertions().len(), 2);
+        assert_eq!(
+            accts
+                .balances()
+                .map(|(id, _)| id.name())
+                .collect::<Vec<_>>(),
+            vec!["Coffee"]
+        );
+    }
+
     fn add_open_account_tokens(
         tokens: &mut Vec<Result<Token, TokenizeError>>,
         date: Date,
@@ -516,24 +2231,28 @@ mod tests {
                 kind: TokenKind::Date(date),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::DirectiveOpen,
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::Account(crate::types::AccountId {
                     type_: account_type,
-                    name: account_name.into(),
+                    components: vec![account_name.into()],
                 }),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::Currency(currency.into()),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
         ];
 
@@ -546,16 +2265,19 @@ mod tests {
                 kind: TokenKind::Date(date),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::DirectivePostTx,
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
-                kind: TokenKind::TxDescription,
+                kind: TokenKind::TxDescription("description".to_string()),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
         ];
 
@@ -573,10 +2295,11 @@ mod tests {
             Ok(Token {
                 kind: TokenKind::Account(crate::types::AccountId {
                     type_: account_type,
-                    name: account_name.into(),
+                    components: vec![account_name.into()],
                 }),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::Amount(Amount {
@@ -585,6 +2308,7 @@ mod tests {
                 }),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
         ];
 
@@ -599,10 +2323,11 @@ mod tests {
         tokens.push(Ok(Token {
             kind: TokenKind::Account(crate::types::AccountId {
                 type_: account_type,
-                name: account_name.into(),
+                components: vec![account_name.into()],
             }),
             line: 0,
             column: 0,
+            span: 0..0,
         }));
     }
 
@@ -619,10 +2344,11 @@ mod tests {
             Ok(Token {
                 kind: TokenKind::Account(crate::types::AccountId {
                     type_: account_type,
-                    name: account_name.into(),
+                    components: vec![account_name.into()],
                 }),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::Amount(Amount {
@@ -631,11 +2357,13 @@ mod tests {
                 }),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::At,
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
             Ok(Token {
                 kind: TokenKind::Amount(Amount {
@@ -644,6 +2372,7 @@ mod tests {
                 }),
                 line: 0,
                 column: 0,
+                span: 0..0,
             }),
         ];
 
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use date::Date;
+use rust_decimal::Decimal;
+
+use crate::types::Amount;
+
+/// A database of prices declared with price directives, which take the form:
+/// ```beancount
+/// 2023-02-01 price EUR 0.85 GBP
+/// ```
+/// meaning that on 2023-02-01 one EUR (the base currency) cost 0.85 GBP (the quote currency).
+/// Prices are keyed by base currency, quote currency and date, with at most one price per key. A
+/// later price for the same key replaces the earlier one.
+#[derive(Debug, Default)]
+pub struct PriceDb {
+    prices: BTreeMap<(String, String), BTreeMap<Date, Decimal>>,
+}
+
+impl PriceDb {
+    pub fn new() -> PriceDb {
+        PriceDb::default()
+    }
+
+    /// Records that on `date` one unit of `base` cost `price`.
+    pub fn add_price(&mut self, date: Date, base: impl Into<String>, price: Amount) {
+        self.prices
+            .entry((base.into(), price.currency))
+            .or_default()
+            .insert(date, price.amount);
+    }
+
+    /// Returns the price of one unit of `base` in `quote` declared on exactly `date`.
+    pub fn price(&self, base: &str, quote: &str, date: Date) -> Option<Decimal> {
+        self.prices
+            .get(&(base.to_string(), quote.to_string()))?
+            .get(&date)
+            .copied()
+    }
+
+    /// Returns the most recent rate, declared on or before `date`, for converting `base` into
+    /// `quote` along with the date it was declared. If only prices of `quote` in `base` have been
+    /// declared then the inverse of the most recent one is returned. A currency always converts
+    /// to itself at a rate of one.
+    pub fn latest_rate(&self, base: &str, quote: &str, date: Date) -> Option<(Date, Decimal)> {
+        if base == quote {
+            return Some((date, Decimal::ONE));
+        }
+
+        let direct = self.latest_price(base, quote, date);
+        let inverse = self
+            .latest_price(quote, base, date)
+            .map(|(date, price)| (date, Decimal::ONE / price));
+
+        match (direct, inverse) {
+            (Some(direct), Some(inverse)) if inverse.0 > direct.0 => Some(inverse),
+            (Some(direct), _) => Some(direct),
+            (None, inverse) => inverse,
+        }
+    }
+
+    /// Converts `amount` into `currency` using the most recent rate declared on or before `date`.
+    /// Returns [`None`] if there's no such rate.
+    pub fn convert(&self, amount: &Amount, currency: &str, date: Date) -> Option<Amount> {
+        let (_, rate) = self.latest_rate(&amount.currency, currency, date)?;
+
+        Some(Amount {
+            currency: currency.to_string(),
+            amount: amount.amount * rate,
+        })
+    }
+
+    fn latest_price(&self, base: &str, quote: &str, date: Date) -> Option<(Date, Decimal)> {
+        self.prices
+            .get(&(base.to_string(), quote.to_string()))?
+            .range(..=date)
+            .next_back()
+            .map(|(date, price)| (*date, *price))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use date::date;
+    use rust_decimal::dec;
+
+    fn gbp(amount: Decimal) -> Amount {
+        Amount {
+            currency: "GBP".to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn latest_rate_works() {
+        let mut prices = PriceDb::new();
+        prices.add_price(date! {2023-02-01}, "EUR", gbp(dec!(0.85)));
+        prices.add_price(date! {2023-02-03}, "EUR", gbp(dec!(0.86)));
+        prices.add_price(date! {2023-02-03}, "EUR", gbp(dec!(0.87)));
+
+        assert_eq!(prices.price("EUR", "GBP", date! {2023-02-02}), None);
+        assert_eq!(
+            prices.price("EUR", "GBP", date! {2023-02-03}),
+            Some(dec!(0.87))
+        );
+
+        assert_eq!(prices.latest_rate("EUR", "GBP", date! {2023-01-31}), None);
+        assert_eq!(
+            prices.latest_rate("EUR", "GBP", date! {2023-02-02}),
+            Some((date! {2023-02-01}, dec!(0.85)))
+        );
+        assert_eq!(
+            prices.latest_rate("EUR", "GBP", date! {2023-03-01}),
+            Some((date! {2023-02-03}, dec!(0.87)))
+        );
+        assert_eq!(
+            prices.latest_rate("GBP", "EUR", date! {2023-02-02}),
+            Some((date! {2023-02-01}, Decimal::ONE / dec!(0.85)))
+        );
+        assert_eq!(
+            prices.latest_rate("USD", "USD", date! {2023-02-02}),
+            Some((date! {2023-02-02}, Decimal::ONE))
+        );
+    }
+
+    #[test]
+    fn convert_works() {
+        let mut prices = PriceDb::new();
+        prices.add_price(date! {2023-02-01}, "EUR", gbp(dec!(0.85)));
+
+        assert_eq!(
+            prices.convert(
+                &Amount {
+                    currency: "EUR".to_string(),
+                    amount: 100.into(),
+                },
+                "GBP",
+                date! {2023-02-01}
+            ),
+            Some(gbp(85.into()))
+        );
+        assert_eq!(
+            prices.convert(&gbp(85.into()), "USD", date! {2023-02-01}),
+            None
+        );
+    }
+}
@@ -1,56 +1,15 @@
-use std::sync::LazyLock;
+use std::{
+    io::{self, BufRead},
+    ops::Range,
+};
 
 use date::Date;
-use regex::Regex;
-use rust_decimal::prelude::Zero;
 
-use crate::types::{AccountId, Amount};
-
-static DATE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"^(\d{4}-\d{2}-\d{2})(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
-});
-
-static AMOUNT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"^(-?(?:\d{1,3}(?:,\d{3})*|\d+)(?:\.\d+)?)[ \t]*([A-Z]{3,})(?:[ \t\n\r]|$)"#)
-        .expect("hard coded regex is valid")
-});
-
-static WHITESPACE_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^[ \t]+"#).expect("hard coded regex is valid"));
-
-static OPTION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"^(option\s+"[^"]+"\s+"[^"]+")(?:[ \t\n\r]|$)"#)
-        .expect("hard coded regex is valid")
-});
-
-static DIRECTIVE_OPEN_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^(open)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid"));
-
-static DIRECTIVE_POST_TX_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^(\*)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid"));
-
-static ACCOUNT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(
-        r#"^(Assets|Liabilities|Expenses|Income|Equity):([A-Z][A-Za-z0-9-]+)(?:[ \t\n\r]|$)"#,
-    )
-    .expect("hard coded regex is valid")
-});
-
-static CURRENCY_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^([A-Z]+)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid"));
-
-static COMMENT_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^;[^\r\n]*"#).expect("hard coded regex is valid"));
-
-static TX_DESCRIPTION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"^("[^"]+")(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
-});
-
-static AT_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^(@)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid"));
-
-static NEWLINE_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^\r?\n"#).expect("hard coded regex is valid"));
+use crate::{
+    lexer::{LexemeKind, is_truncated, lex},
+    options::RootNames,
+    types::{AccountId, Amount},
+};
 
 /// The error returned if the tokenizer fetches the next token.
 #[derive(Debug, PartialEq)]
@@ -58,6 +17,29 @@ pub struct TokenizeError {
     pub(crate) msg: String,
     pub(crate) line: usize,
     pub(crate) column: usize,
+    pub(crate) span: Range<usize>,
+}
+
+impl TokenizeError {
+    /// Returns the error message.
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+
+    /// Returns the one-indexed line number of the start of the error.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the one-indexed column of the start of the error.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the byte range of the source which caused the error.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
 }
 
 impl std::fmt::Display for TokenizeError {
@@ -68,30 +50,111 @@ impl std::fmt::Display for TokenizeError {
 
 impl std::error::Error for TokenizeError {}
 
-/// The kind of [`Token`].
+/// The kind of [`Token`]. The values are owned, so that the parser can move them into the
+/// [`AccountsDocument`](crate::accounts_doc::AccountsDocument) as they are. Making them is the
+/// only allocation for each token, since the lexer borrows from the source.
 #[derive(Debug, PartialEq)]
 pub enum TokenKind {
     Date(Date),
     Amount(Amount),
     DirectiveOpen,
+    DirectiveBalance,
+    DirectiveClose,
+    DirectivePad,
+    DirectivePrice,
+    DirectiveInclude,
     DirectivePostTx,
     Account(AccountId),
     Currency(String),
     At,
+    DoubleAt,
+    Comma,
+    LeftBrace,
+    RightBrace,
+    DoubleLeftBrace,
+    DoubleRightBrace,
     Newline,
-    OptionLine,
-    TxDescription,
+    /// An option line e.g. `option "title" "My Accounts"`. Holds the name and the value of the
+    /// option, without the surrounding quotes.
+    OptionLine {
+        name: String,
+        value: String,
+    },
+    /// A transaction payee or narration. Holds the unescaped contents of the string, without the
+    /// surrounding quotes.
+    TxDescription(String),
+}
+
+/// Describes the kind of token, used in error messages e.g. "expected currency, found newline".
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TokenKind::Date(_) => "date",
+            TokenKind::Amount(_) => "amount",
+            TokenKind::DirectiveOpen => "`open`",
+            TokenKind::DirectiveBalance => "`balance`",
+            TokenKind::DirectiveClose => "`close`",
+            TokenKind::DirectivePad => "`pad`",
+            TokenKind::DirectivePrice => "`price`",
+            TokenKind::DirectiveInclude => "`include`",
+            TokenKind::DirectivePostTx => "`*`",
+            TokenKind::Account(_) => "account",
+            TokenKind::Currency(_) => "currency",
+            TokenKind::At => "`@`",
+            TokenKind::DoubleAt => "`@@`",
+            TokenKind::Comma => "`,`",
+            TokenKind::LeftBrace => "`{`",
+            TokenKind::RightBrace => "`}`",
+            TokenKind::DoubleLeftBrace => "`{{`",
+            TokenKind::DoubleRightBrace => "`}}`",
+            TokenKind::Newline => "newline",
+            TokenKind::OptionLine { .. } => "option line",
+            TokenKind::TxDescription(_) => "string",
+        })
+    }
 }
 
-/// The tokens returned by [`Tokenizer`].
+/// The tokens returned by [`Tokenizer`]. The `line` and `column` are the one-indexed position of
+/// the start of the token, where the column counts characters i.e. Unicode scalar values. The
+/// `span` is the byte range of the token in the source, which [`LineIndex`] can turn into other
+/// kinds of column such as the UTF-16 columns used by editors.
+///
+/// [`LineIndex`]: crate::line_index::LineIndex
 #[derive(Debug, PartialEq)]
 pub struct Token {
     pub kind: TokenKind,
     pub line: usize,
     pub column: usize,
+    pub span: Range<usize>,
+}
+
+/// The kind of [`Trivia`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    /// Spaces and tabs.
+    Whitespace,
+    /// A comment, from the `;` up to the end of the line.
+    Comment,
+    /// A newline which ends a blank line. The tokenizer returns these as [`TokenKind::Newline`]
+    /// tokens, since a blank line ends a transaction, and only [`crate::cst`] turns them into
+    /// trivia.
+    Newline,
+    /// The rest of a line which was skipped after a [`TokenizeError`].
+    Skipped,
+}
+
+/// Source which isn't part of any [`Token`], such as whitespace and comments. The `span` is the
+/// byte range of the trivia in the source. Trivia is only kept if the tokenizer is asked to with
+/// [`Tokenizer::keep_trivia`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Range<usize>,
 }
 
 /// Creates [`Token`]s from a raw [`String`]. Tokenizer implements [`Iterator`], yielding a [`Result<Token, TokenizerError>`].
+/// After an error the rest of the offending line is skipped, so iteration can continue in order
+/// to find any further errors.
 ///
 /// # Examples
 ///
@@ -109,20 +172,23 @@ pub struct Token {
 ///                Token {
 ///                    kind: TokenKind::Date(date!{2023-02-01}),
 ///                    line: 1,
-///                    column: 1
+///                    column: 1,
+///                    span: 0..10,
 ///                },
 ///                Token {
 ///                    kind: TokenKind::DirectiveOpen,
 ///                    line: 1,
-///                    column: 11
+///                    column: 12,
+///                    span: 11..15,
 ///                },
 ///                Token {
 ///                    kind: TokenKind::Account(AccountId {
-///                        name: "RetainedEarnings".to_string(),
+///                        components: vec!["RetainedEarnings".to_string()],
 ///                        type_: Equity
 ///                    }),
 ///                    line: 1,
-///                    column: 16
+///                    column: 17,
+///                    span: 16..39,
 ///                }
 ///            ],
 ///            tokens
@@ -130,6 +196,12 @@ pub struct Token {
 /// ```
 pub struct Tokenizer {
     buffer: String,
+    // The reader which the buffer is filled from a line at a time. It's `None` once it's been read
+    // to the end, or if the tokenizer was created with the whole buffer.
+    reader: Option<Box<dyn BufRead>>,
+    // The number of bytes which have been dropped from the start of the buffer after they were
+    // tokenized. Adding it to a position in the buffer gives the position in the whole source.
+    offset: usize,
     // The cursor represents a position between characters, not the character itself. For a string of
     // length n there are n+1 valid cursor positions.
     // - The cursor at position 0 refers to the start of the buffer (before the first character).
@@ -137,75 +209,171 @@ pub struct Tokenizer {
     // - A cursor at the end of the buffer (i.e., cursor == buffer.len()) is valid and refers to the position after the last character.
     // Characters before the cursor have been processed those after have not.
     cursor: usize,
+    // The line and column the cursor was on when the position was last tracked, the index of the
+    // start of that line, and the cursor position at that time.
+    line: usize,
+    column: usize,
+    line_start: usize,
+    tracked: usize,
+    // The names of the root accounts, which are changed by `name_*` option lines as they're read
+    root_names: RootNames,
+    // The trivia found since it was last taken, or `None` if trivia isn't being kept
+    trivia: Option<Vec<Trivia>>,
 }
 
 impl Tokenizer {
     /// Tokenizer constructor.
     pub fn new(buffer: impl Into<String>) -> Self {
+        Tokenizer::with_root_names(buffer, RootNames::default())
+    }
+
+    /// Creates a tokenizer which recognises accounts under the root accounts named `root_names`.
+    /// Option lines such as `option "name_assets" "Actifs"` change the names used from then on.
+    pub fn with_root_names(buffer: impl Into<String>, root_names: RootNames) -> Self {
         Tokenizer {
             buffer: buffer.into(),
+            reader: None,
+            offset: 0,
             cursor: 0,
+            line: 1,
+            column: 1,
+            line_start: 0,
+            tracked: 0,
+            root_names,
+            trivia: None,
         }
     }
 
+    /// Creates a tokenizer which reads the source from `reader` as it's needed, such as standard
+    /// input or a decompressing reader. Lines are dropped once they've been tokenized, so the
+    /// memory used is bounded by the longest line. Strings and option lines can span lines, in
+    /// which case every line up to the end of the string is kept. Spans are positions in the
+    /// whole source, as if it had been read into a [`String`].
+    ///
+    /// An error reading from `reader`, such as the source not being UTF-8, is returned as a
+    /// [`TokenizeError`] and nothing more is read.
+    pub fn from_reader(reader: impl BufRead + 'static) -> Self {
+        Tokenizer {
+            reader: Some(Box::new(reader)),
+            ..Tokenizer::new(String::new())
+        }
+    }
+
+    /// Keeps the trivia between the tokens, which is dropped otherwise, so that it can be taken
+    /// with [`Tokenizer::take_trivia`]. The tokens returned are the same either way.
+    pub fn keep_trivia(mut self) -> Self {
+        self.trivia = Some(vec![]);
+        self
+    }
+
+    /// Returns the trivia found since the trivia was last taken, in the order of the source. This
+    /// is the trivia before the last token returned, along with the rest of the line after an
+    /// error. It's always empty unless the tokenizer was created with [`Tokenizer::keep_trivia`].
+    pub fn take_trivia(&mut self) -> Vec<Trivia> {
+        self.trivia.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Returns the names of the root accounts currently recognised.
+    pub fn root_names(&self) -> &RootNames {
+        &self.root_names
+    }
+
+    /// Changes the names of the root accounts recognised from the next token on.
+    pub(crate) fn set_root_names(&mut self, root_names: RootNames) {
+        self.root_names = root_names;
+    }
+
     /// Returns the current one-indexed line number and column of the cursor as a (line_number, column) tuple.
+    /// If the cursor is at the start of the buffer then it's at line one, column one. Every newline
+    /// character crossed increments the line number and resets the column to one, and each other
+    /// character crossed increments the column.
+    ///
+    /// The position must have been tracked since the cursor last moved, see
+    /// [`Tokenizer::track_position`].
     fn current_line_column(&self) -> (usize, usize) {
-        (self.current_line(), self.current_column())
-    }
-
-    /// Returns the current one-indexed line number of the cursor. If the cursor is at the start
-    /// of the buffer then it's on line one. Every newline character represents an increment of one
-    /// in the line count - this includes cases where the last character is a newline and the
-    /// cursor increments to the end of the buffer.
-    fn current_line(&self) -> usize {
-        if self.cursor.is_zero() {
-            // If the cursor is at zero i.e. the start of the buffer then below we would calculate
-            // "".lines().count() as zero. However we want to count the start of the buffer as line
-            // one.
-            1
-        } else {
-            let lines = self.buffer[0..self.cursor].lines().count();
-
-            // There's a nasty edge case here. If the string ends in a newline then the lines()
-            // method doesn't return an empty line after the last newline. This means the line
-            // count is one less than we expect (since the cursor is beyond the last newline we
-            // want to count it as being on the next line) e.g.
-            // "hello\n".lines().count() returns one whereas we expect this to count as two lines.
-            // We check for this case and fix accordingly.
-            if self.buffer[0..self.cursor].ends_with("\n") {
-                lines + 1
-            } else {
-                lines
+        debug_assert_eq!(self.tracked, self.cursor, "the position must be tracked");
+        (self.line, self.column)
+    }
+
+    /// Brings the line tracking up to date with the cursor by counting the newlines and characters
+    /// the cursor has moved past since the position was last tracked. This is done at the start of each
+    /// token. The cursor only moves forwards, so each byte is only looked at once and tokenizing
+    /// takes time linear in the size of the buffer.
+    fn track_position(&mut self) {
+        if self.cursor < self.tracked {
+            // Only the tests move the cursor backwards, in which case start again from the top
+            self.line = 1;
+            self.column = 1;
+            self.line_start = 0;
+            self.tracked = 0;
+        }
+
+        let crossed = &self.buffer.as_bytes()[self.tracked..self.cursor];
+        for (index, byte) in crossed.iter().enumerate() {
+            if *byte == b'\n' {
+                self.line += 1;
+                self.column = 1;
+                self.line_start = self.tracked + index + 1;
+            } else if !is_continuation_byte(*byte) {
+                self.column += 1;
             }
         }
+        self.tracked = self.cursor;
     }
 
-    /// Returns the one-indexed column of the cursor. If the cursor is at the start of the buffer then
-    /// it's at column one. Each increment of the cursor position increments the column number.
-    /// When a newline character is crossed the column counter resets to one.
-    fn current_column(&self) -> usize {
-        if self.cursor.is_zero() {
-            // If the cursor is at zero then `line_start` below would be zero and so the returned
-            // value would also be zero. However if the cursor is at the start of the buffer then
-            // it's by definition at column one. So we override for this edge case.
-            1
-        } else {
-            // Find the start index of the current line.
-            let line_start_index = self.buffer[..self.cursor].rfind('\n').unwrap_or(0); // or start of buffer
-            // If the cursor has value n then the character before the cursor has index n-1
-            // (think of the base case where the cursor has value one then the single character
-            // before it has index zero). So if the cursor is immediately to the right of a newline
-            // character the line_start_index will be one less than the cursor value. Subtraction
-            // would then give a value of one which is the correct column value.
-            // TODO: this gives the number of bytes not the number of graphemes or even unicode
-            // points
-            self.cursor - line_start_index
+    /// Reads the next line from the reader onto the end of the buffer. The lines before the
+    /// cursor's line are dropped first, so the position must have been tracked. Returns `false`
+    /// if there's nothing more to read.
+    fn read_line(&mut self) -> io::Result<bool> {
+        let Some(reader) = &mut self.reader else {
+            return Ok(false);
+        };
+
+        if self.line_start > 0 {
+            self.buffer.drain(..self.line_start);
+            self.offset += self.line_start;
+            self.cursor -= self.line_start;
+            self.tracked -= self.line_start;
+            self.line_start = 0;
+        }
+
+        match reader.read_line(&mut self.buffer) {
+            Ok(0) => {
+                self.reader = None;
+                Ok(false)
+            }
+            Ok(_) => Ok(true),
+            Err(e) => {
+                self.reader = None;
+                Err(e)
+            }
         }
     }
 
+    /// Moves the cursor to the end of the current line i.e. to just before the newline, or to the
+    /// end of the buffer if this is the last line. This is used after an error so that the
+    /// next call to [`Tokenizer::next_token`] carries on from the next line rather than failing
+    /// again at the same position.
+    fn skip_to_end_of_line(&mut self) {
+        self.cursor = match self.buffer[self.cursor..].find('\n') {
+            Some(index) => {
+                let newline = self.cursor + index;
+                // Stop before the carriage return of a "\r\n" line ending so that it's
+                // tokenized as a newline.
+                if newline > self.cursor && self.buffer[..newline].ends_with('\r') {
+                    newline - 1
+                } else {
+                    newline
+                }
+            }
+            None => self.buffer.len(),
+        };
+    }
+
     #[cfg(test)]
     fn set_cursor(&mut self, pos: usize) {
         self.cursor = pos;
+        self.track_position();
     }
 }
 
@@ -219,187 +387,169 @@ impl Iterator for Tokenizer {
 
 impl Tokenizer {
     fn next_token(&mut self) -> Result<Option<Token>, TokenizeError> {
-        if self.cursor >= self.buffer.len() {
-            Ok(None)
-        } else if let Some(whitespace) = WHITESPACE_REGEX.find(&self.buffer[self.cursor..]) {
-            self.cursor += whitespace.end();
-            self.next_token()
-        } else if let Some(option_line) =
-            OPTION_REGEX.captures(&self.buffer[self.cursor..]).map(|c| {
-                c.get(1).expect(
-                    "if the entire regex matches then the first capture group will not be None",
-                )
-            })
-        {
-            let (line, column) = self.current_line_column();
-            self.cursor += option_line.end();
-            Ok(Some(Token {
-                kind: TokenKind::OptionLine,
-                line,
-                column,
-            }))
-        } else if let Some(comment) = COMMENT_REGEX.find(&self.buffer[self.cursor..]) {
-            // we ignore comments
-            self.cursor += comment.end();
-            self.next_token()
-        } else if let Some(date) = DATE_REGEX.captures(&self.buffer[self.cursor..]).map(|c| {
-            c.get(1)
-                .expect("if the entire regex matches then the first capture group will not be None")
-        }) {
+        loop {
+            self.track_position();
+
+            // The buffer always holds the whole of the cursor's line. Strings and option lines
+            // can go on to the following lines, which are read until they're complete.
+            let mut read = Ok(true);
+            if self.cursor == self.buffer.len() {
+                read = self.read_line();
+            }
+            while read.is_ok() && self.reader.is_some() && is_truncated(&self.buffer[self.cursor..])
+            {
+                read = self.read_line();
+            }
             let (line, column) = self.current_line_column();
-            self.cursor += date.end();
-            let date = date
-                .as_str()
-                .parse()
-                .expect("the regex guarantees that parsing won't fail");
-            Ok(Some(Token {
-                kind: TokenKind::Date(date),
-                line,
-                column,
-            }))
-        } else if let Some(captures) = AMOUNT_REGEX.captures(&self.buffer[self.cursor..]) {
-            let amount = captures
-                .get(1)
-                .expect("if there was a match there will be a 1 capture group");
-            let currency = captures
-                .get(2)
-                .expect("if there was a match there will be a 1 capture group");
-            let Ok(amount) = amount.as_str().replace(",", "").parse() else {
-                // the regex accepts commas e.g. 9,000 which won't parse so we strip them out
-                let (line, column) = self.current_line_column();
+            if let Err(e) = read {
+                let position = self.offset + self.cursor;
                 return Err(TokenizeError {
-                    msg: "decimal has too many digits".to_string(),
+                    msg: format!("can't read the source: {e}"),
                     line,
                     column,
+                    span: position..position,
                 });
-            };
-            let (line, column) = self.current_line_column();
-            self.cursor += currency.end();
-            Ok(Some(Token {
-                kind: TokenKind::Amount(Amount {
-                    currency: currency.as_str().to_string(),
-                    amount,
-                }),
-                line,
-                column,
-            }))
-        } else if let Some(directive_open) = DIRECTIVE_OPEN_REGEX
-            .captures(&self.buffer[self.cursor..])
-            .map(|c| {
-                c.get(1).expect(
-                    "if the entire regex matches then the first capture group will not be None",
-                )
-            })
-        {
-            let (line, column) = self.current_line_column();
-            self.cursor += directive_open.end();
-            Ok(Some(Token {
-                kind: TokenKind::DirectiveOpen,
-                line,
-                column,
-            }))
-        } else if let Some(directive_post_tx) = DIRECTIVE_POST_TX_REGEX
-            .captures(&self.buffer[self.cursor..])
-            .map(|c| {
-                c.get(1).expect(
-                    "if the entire regex matches then the first capture group will not be None",
-                )
-            })
-        {
-            let (line, column) = self.current_line_column();
-            self.cursor += directive_post_tx.end();
-            Ok(Some(Token {
-                kind: TokenKind::DirectivePostTx,
-                line,
-                column,
-            }))
-        } else if let Some(full_account) = ACCOUNT_REGEX.captures(&self.buffer[self.cursor..]) {
-            let acct_type = full_account
-                .get(1)
-                .expect("if there was a match there will be a 1 capture group")
-                .as_str()
-                .parse()
-                .expect("the regex guarantees that parsing won't fail");
-
-            let acct_name = full_account
-                .get(2)
-                .expect("if there was a match there will be a 2 capture group");
+            }
 
-            let (line, column) = self.current_line_column();
-            self.cursor += acct_name.end();
-            let acct_name = acct_name.as_str();
+            let start = self.cursor;
+            if start >= self.buffer.len() {
+                return Ok(None);
+            }
 
-            Ok(Some(Token {
-                kind: TokenKind::Account(AccountId {
-                    type_: acct_type,
-                    name: acct_name.to_string(),
+            let lexeme = lex(&self.buffer[start..], &self.root_names);
+            // The position of the start of the lexeme in the whole source
+            let position = self.offset + start;
+            let kind = match lexeme.kind {
+                // whitespace and comments are ignored, unless they're kept as trivia
+                LexemeKind::Whitespace | LexemeKind::Comment => {
+                    let kind = match lexeme.kind {
+                        LexemeKind::Whitespace => TriviaKind::Whitespace,
+                        _ => TriviaKind::Comment,
+                    };
+                    push_trivia(&mut self.trivia, kind, position..position + lexeme.len);
+                    self.cursor += lexeme.len;
+                    continue;
+                }
+                LexemeKind::Newline => TokenKind::Newline,
+                LexemeKind::OptionLine { name, value } => {
+                    // Invalid names are left unchanged, the parser reports them
+                    let _ = self.root_names.set(name, value);
+                    TokenKind::OptionLine {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    }
+                }
+                LexemeKind::Date(date) => TokenKind::Date(
+                    date.parse()
+                        .expect("the lexer guarantees the date has the form YYYY-MM-DD"),
+                ),
+                LexemeKind::Amount { number, currency } => {
+                    // the lexer accepts commas e.g. 9,000 which won't parse so we strip them out
+                    let Ok(amount) = number.replace(',', "").parse() else {
+                        let span = position..position + lexeme.len;
+                        self.skip_to_end_of_line();
+                        let skipped = span.end..self.offset + self.cursor;
+                        push_trivia(&mut self.trivia, TriviaKind::Skipped, skipped);
+                        return Err(TokenizeError {
+                            msg: "decimal has too many digits".to_string(),
+                            line,
+                            column,
+                            span,
+                        });
+                    };
+                    TokenKind::Amount(Amount {
+                        currency: currency.to_string(),
+                        amount,
+                    })
+                }
+                LexemeKind::DirectiveOpen => TokenKind::DirectiveOpen,
+                LexemeKind::DirectiveBalance => TokenKind::DirectiveBalance,
+                LexemeKind::DirectiveClose => TokenKind::DirectiveClose,
+                LexemeKind::DirectivePad => TokenKind::DirectivePad,
+                LexemeKind::DirectivePrice => TokenKind::DirectivePrice,
+                LexemeKind::DirectiveInclude => TokenKind::DirectiveInclude,
+                LexemeKind::DirectivePostTx => TokenKind::DirectivePostTx,
+                LexemeKind::Account { type_, components } => TokenKind::Account(AccountId {
+                    type_,
+                    components: components.split(':').map(str::to_string).collect(),
                 }),
+                LexemeKind::Currency(currency) => TokenKind::Currency(currency.to_string()),
+                LexemeKind::TxDescription(description) => {
+                    TokenKind::TxDescription(unescape(description))
+                }
+                LexemeKind::At => TokenKind::At,
+                LexemeKind::DoubleAt => TokenKind::DoubleAt,
+                LexemeKind::Comma => TokenKind::Comma,
+                LexemeKind::LeftBrace => TokenKind::LeftBrace,
+                LexemeKind::RightBrace => TokenKind::RightBrace,
+                LexemeKind::DoubleLeftBrace => TokenKind::DoubleLeftBrace,
+                LexemeKind::DoubleRightBrace => TokenKind::DoubleRightBrace,
+                LexemeKind::Unknown => {
+                    // The error spans the unrecognized sequence i.e. up to the next whitespace
+                    // character.
+                    let span = position..position + lexeme.len;
+                    self.skip_to_end_of_line();
+                    let skipped = span.end..self.offset + self.cursor;
+                    push_trivia(&mut self.trivia, TriviaKind::Skipped, skipped);
+                    return Err(TokenizeError {
+                        msg: "unexpected character sequence".to_string(),
+                        line,
+                        column,
+                        span,
+                    });
+                }
+            };
+
+            self.cursor += lexeme.len;
+            return Ok(Some(Token {
+                kind,
                 line,
                 column,
-            }))
-        } else if let Some(currency) =
-            CURRENCY_REGEX
-                .captures(&self.buffer[self.cursor..])
-                .map(|c| {
-                    c.get(1).expect(
-                        "if the entire regex matches then the first capture group will not be None",
-                    )
-                })
-        {
-            let (line, column) = self.current_line_column();
-            self.cursor += currency.end();
-            Ok(Some(Token {
-                kind: TokenKind::Currency(currency.as_str().to_string()),
-                line,
-                column,
-            }))
-        } else if let Some(tx_description) = TX_DESCRIPTION_REGEX
-            .captures(&self.buffer[self.cursor..])
-            .map(|c| {
-                c.get(1).expect(
-                    "if the entire regex matches then the first capture group will not be None",
-                )
-            })
-        {
-            let (line, column) = self.current_line_column();
-            self.cursor += tx_description.end();
-            Ok(Some(Token {
-                kind: TokenKind::TxDescription,
-                line,
-                column,
-            }))
-        } else if let Some(at) = AT_REGEX.captures(&self.buffer[self.cursor..]).map(|c| {
-            c.get(1)
-                .expect("if the entire regex matches then the first capture group will not be None")
-        }) {
-            let (line, column) = self.current_line_column();
-            self.cursor += at.end();
-            Ok(Some(Token {
-                kind: TokenKind::At,
-                line,
-                column,
-            }))
-        } else if let Some(newline) = NEWLINE_REGEX.find(&self.buffer[self.cursor..]) {
-            let (line, column) = self.current_line_column();
-            self.cursor += newline.end();
-            Ok(Some(Token {
-                kind: TokenKind::Newline,
-                line,
-                column,
-            }))
+                span: position..position + lexeme.len,
+            }));
+        }
+    }
+}
+
+/// Adds trivia spanning `span` of the source to `trivia`, if trivia is being kept and the span
+/// isn't empty.
+fn push_trivia(trivia: &mut Option<Vec<Trivia>>, kind: TriviaKind, span: Range<usize>) {
+    if let Some(trivia) = trivia
+        && !span.is_empty()
+    {
+        trivia.push(Trivia { kind, span });
+    }
+}
+
+/// Returns `true` if `byte` is in the middle of a UTF-8 encoded character rather than at its start.
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// Replaces the escape sequences `\"` and `\\` in the contents of a string with `"` and `\`
+/// respectively. Any other backslash is left as is.
+fn unescape(raw: &str) -> String {
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(escaped @ ('"' | '\\')) => unescaped.push(escaped),
+                Some(other) => {
+                    unescaped.push(c);
+                    unescaped.push(other);
+                }
+                None => unescaped.push(c),
+            }
         } else {
-            let (line, column) = self.current_line_column();
-            Err(TokenizeError {
-                msg: "unexpected character sequence".to_string(),
-                line,
-                column,
-            })
+            unescaped.push(c);
         }
     }
+    unescaped
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::types::AccountType;
     use date::date;
@@ -417,20 +567,23 @@ mod tests {
                 Token {
                     kind: TokenKind::Date(date! {2023-02-01}),
                     line: 1,
-                    column: 1
+                    column: 1,
+                    span: 0..10,
                 },
                 Token {
                     kind: TokenKind::DirectiveOpen,
                     line: 1,
-                    column: 11
+                    column: 12,
+                    span: 11..15,
                 },
                 Token {
                     kind: TokenKind::Account(AccountId {
-                        name: "RetainedEarnings".to_string(),
+                        components: vec!["RetainedEarnings".to_string()],
                         type_: Equity
                     }),
                     line: 1,
-                    column: 16
+                    column: 17,
+                    span: 16..39,
                 }
             ],
             tokens
@@ -455,9 +608,13 @@ mod tests {
         assert_eq!(
             tokenizer.next_token(),
             Ok(Some(Token {
-                kind: TokenKind::OptionLine,
+                kind: TokenKind::OptionLine {
+                    name: "operating_currency".to_string(),
+                    value: "GBP".to_string(),
+                },
                 line: 1,
                 column: 1,
+                span: 0..33,
             })),
         );
 
@@ -466,7 +623,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 1,
-                column: 33
+                column: 34,
+                span: 33..34,
             })),
         );
 
@@ -475,7 +633,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 2,
-                column: 1
+                column: 1,
+                span: 34..35,
             })),
         );
 
@@ -485,6 +644,7 @@ mod tests {
                 kind: TokenKind::Date(date! {2023-02-01}),
                 line: 3,
                 column: 1,
+                span: 35..45,
             }))
         );
 
@@ -493,7 +653,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::DirectiveOpen,
                 line: 3,
-                column: 12
+                column: 12,
+                span: 46..50,
             }))
         );
 
@@ -502,10 +663,11 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Account(AccountId {
                     type_: AccountType::Equity,
-                    name: "RetainedEarnings".to_string()
+                    components: vec!["RetainedEarnings".to_string()]
                 }),
                 line: 3,
-                column: 17
+                column: 17,
+                span: 51..74,
             }))
         );
 
@@ -514,7 +676,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Currency("GBP".to_string()),
                 line: 3,
-                column: 54
+                column: 54,
+                span: 88..91,
             }))
         );
 
@@ -524,6 +687,7 @@ mod tests {
                 kind: TokenKind::Newline,
                 line: 3,
                 column: 69,
+                span: 103..104,
             })),
         );
 
@@ -532,7 +696,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 4,
-                column: 1
+                column: 1,
+                span: 104..105,
             })),
         );
 
@@ -541,7 +706,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 5,
-                column: 40
+                column: 40,
+                span: 144..145,
             })),
         );
 
@@ -550,7 +716,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 6,
-                column: 19
+                column: 19,
+                span: 163..164,
             })),
         );
 
@@ -559,7 +726,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 7,
-                column: 1
+                column: 1,
+                span: 164..165,
             })),
         );
 
@@ -568,7 +736,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Date(date! {2023-02-03}),
                 line: 8,
-                column: 1
+                column: 1,
+                span: 165..175,
             }))
         );
 
@@ -577,16 +746,18 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::DirectivePostTx,
                 line: 8,
-                column: 12
+                column: 12,
+                span: 176..177,
             }))
         );
 
         assert_eq!(
             tokenizer.next_token(),
             Ok(Some(Token {
-                kind: TokenKind::TxDescription,
+                kind: TokenKind::TxDescription("Transaction description".to_string()),
                 line: 8,
-                column: 14
+                column: 14,
+                span: 178..203,
             }))
         );
 
@@ -595,7 +766,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 8,
-                column: 39
+                column: 39,
+                span: 203..204,
             })),
         );
 
@@ -604,10 +776,11 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Account(AccountId {
                     type_: AccountType::Asset,
-                    name: "AnAsset".to_string()
+                    components: vec!["AnAsset".to_string()]
                 }),
                 line: 9,
-                column: 3
+                column: 3,
+                span: 206..220,
             }))
         );
 
@@ -619,7 +792,8 @@ mod tests {
                     amount: "12".parse().expect("hard coded value is a valid decimal")
                 }),
                 line: 9,
-                column: 52
+                column: 52,
+                span: 255..261,
             }))
         );
 
@@ -628,7 +802,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::At,
                 line: 9,
-                column: 59
+                column: 59,
+                span: 262..263,
             }))
         );
 
@@ -640,7 +815,8 @@ mod tests {
                     amount: "0.82".parse().expect("hard coded value is a valid decimal")
                 }),
                 line: 9,
-                column: 61
+                column: 61,
+                span: 264..272,
             }))
         );
 
@@ -649,7 +825,8 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Newline,
                 line: 9,
-                column: 69
+                column: 69,
+                span: 272..273,
             }))
         );
 
@@ -658,10 +835,11 @@ mod tests {
             Ok(Some(Token {
                 kind: TokenKind::Account(AccountId {
                     type_: AccountType::Income,
-                    name: "SomeIncome".to_string()
+                    components: vec!["SomeIncome".to_string()]
                 }),
                 line: 10,
-                column: 3
+                column: 3,
+                span: 275..292,
             }))
         );
 
@@ -675,7 +853,8 @@ mod tests {
                         .expect("hard coded value is a valid decimal")
                 }),
                 line: 10,
-                column: 57
+                column: 57,
+                span: 329..342,
             }))
         );
 
@@ -687,6 +866,178 @@ mod tests {
         );
     }
 
+    #[test]
+    fn balance_directive() {
+        let tokenizer = Tokenizer::new("2023-02-01 balance Assets:Checking 1,234.56 GBP\n");
+        let kinds = tokenizer
+            .map(|t| t.map(|t| t.kind))
+            .collect::<Result<Vec<TokenKind>, TokenizeError>>()
+            .unwrap();
+
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Date(date! {2023-02-01}),
+                TokenKind::DirectiveBalance,
+                TokenKind::Account(AccountId {
+                    components: vec!["Checking".to_string()],
+                    type_: AccountType::Asset
+                }),
+                TokenKind::Amount(Amount {
+                    currency: "GBP".to_string(),
+                    amount: "1234.56"
+                        .parse()
+                        .expect("hard coded value is a valid decimal")
+                }),
+                TokenKind::Newline
+            ]
+        );
+    }
+
+    #[test]
+    fn close_directive() {
+        let tokenizer = Tokenizer::new("2023-02-01 close Liabilities:CreditCard");
+        let kinds = tokenizer
+            .map(|t| t.map(|t| t.kind))
+            .collect::<Result<Vec<TokenKind>, TokenizeError>>()
+            .unwrap();
+
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Date(date! {2023-02-01}),
+                TokenKind::DirectiveClose,
+                TokenKind::Account(AccountId {
+                    components: vec!["CreditCard".to_string()],
+                    type_: AccountType::Liability
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn include_directive() {
+        let tokenizer = Tokenizer::new("include \"accounts/*.beancount\"");
+        let kinds = tokenizer
+            .map(|t| t.map(|t| t.kind))
+            .collect::<Result<Vec<TokenKind>, TokenizeError>>()
+            .unwrap();
+
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::DirectiveInclude,
+                TokenKind::TxDescription("accounts/*.beancount".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn cost_specs() {
+        let tokenizer = Tokenizer::new("10 VWRL {85.20 GBP, 2023-02-01} {{852 GBP}} {}");
+        let kinds = tokenizer
+            .map(|t| t.map(|t| t.kind))
+            .collect::<Result<Vec<TokenKind>, TokenizeError>>()
+            .unwrap();
+
+        let amount = |amount: &str, currency: &str| {
+            TokenKind::Amount(Amount {
+                currency: currency.to_string(),
+                amount: amount.parse().unwrap(),
+            })
+        };
+        assert_eq!(
+            kinds,
+            vec![
+                amount("10", "VWRL"),
+                TokenKind::LeftBrace,
+                amount("85.20", "GBP"),
+                TokenKind::Comma,
+                TokenKind::Date(date! {2023-02-01}),
+                TokenKind::RightBrace,
+                TokenKind::DoubleLeftBrace,
+                amount("852", "GBP"),
+                TokenKind::DoubleRightBrace,
+                TokenKind::LeftBrace,
+                TokenKind::RightBrace,
+            ]
+        );
+    }
+
+    #[test]
+    fn root_names() {
+        let tokenizer = Tokenizer::new(
+            "Assets:Bank\noption \"name_assets\" \"Actifs\"\noption \"name_expenses\" \"Dépenses\"\n\
+             Actifs:Banque Dépenses:Loyer Assets:Bank",
+        );
+        let kinds = tokenizer
+            .map(|t| t.map(|t| t.kind))
+            .collect::<Vec<Result<TokenKind, TokenizeError>>>();
+
+        let account = |type_, component: &str| {
+            Ok(TokenKind::Account(AccountId {
+                components: vec![component.to_string()],
+                type_,
+            }))
+        };
+        assert_eq!(kinds[0], account(AccountType::Asset, "Bank"));
+        assert_eq!(kinds[6], account(AccountType::Asset, "Banque"));
+        assert_eq!(kinds[7], account(AccountType::Expense, "Loyer"));
+        // The default names are no longer recognised once they've been replaced
+        assert!(kinds[8].is_err());
+    }
+
+    #[test]
+    fn total_price() {
+        let tokenizer = Tokenizer::new("100 EUR @@ 85.13 GBP @ 0.85 GBP @@@");
+        let kinds = tokenizer
+            .map(|t| t.map(|t| t.kind))
+            .collect::<Vec<Result<TokenKind, TokenizeError>>>();
+
+        let amount = |amount: &str, currency: &str| {
+            Ok(TokenKind::Amount(Amount {
+                currency: currency.to_string(),
+                amount: amount.parse().unwrap(),
+            }))
+        };
+        assert_eq!(
+            kinds[..5],
+            [
+                amount("100", "EUR"),
+                Ok(TokenKind::DoubleAt),
+                amount("85.13", "GBP"),
+                Ok(TokenKind::At),
+                amount("0.85", "GBP"),
+            ]
+        );
+        assert!(kinds[5].is_err());
+    }
+
+    #[test]
+    fn pad_directive() {
+        let tokenizer = Tokenizer::new("2023-02-01 pad Assets:Checking Equity:Opening-Balances");
+        let kinds = tokenizer
+            .map(|t| t.map(|t| t.kind))
+            .collect::<Result<Vec<TokenKind>, TokenizeError>>()
+            .unwrap();
+
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Date(date! {2023-02-01}),
+                TokenKind::DirectivePad,
+                TokenKind::Account(AccountId {
+                    components: vec!["Checking".to_string()],
+                    type_: AccountType::Asset
+                }),
+                TokenKind::Account(AccountId {
+                    components: vec!["Opening-Balances".to_string()],
+                    type_: AccountType::Equity
+                }),
+            ]
+        );
+    }
+
     #[test]
     fn ensure_error_when_decimal_too_many_digits() {
         let mut tokenizer = Tokenizer::new("79228162514264337593543950336GBP".to_string());
@@ -697,6 +1048,7 @@ mod tests {
                 msg: "decimal has too many digits".to_string(),
                 line: 1,
                 column: 1,
+                span: 0..32,
             }
         )
     }
@@ -711,6 +1063,7 @@ mod tests {
                 msg: "unexpected character sequence".to_string(),
                 line: 1,
                 column: 1,
+                span: 0..13,
             }
         );
 
@@ -721,6 +1074,7 @@ mod tests {
                 msg: "unexpected character sequence".to_string(),
                 line: 1,
                 column: 1,
+                span: 0..14,
             }
         );
 
@@ -731,6 +1085,7 @@ mod tests {
                 msg: "unexpected character sequence".to_string(),
                 line: 1,
                 column: 1,
+                span: 0..5,
             }
         );
 
@@ -741,6 +1096,7 @@ mod tests {
                 msg: "unexpected character sequence".to_string(),
                 line: 1,
                 column: 1,
+                span: 0..6,
             }
         );
 
@@ -751,10 +1107,46 @@ mod tests {
                 msg: "unexpected character sequence".to_string(),
                 line: 1,
                 column: 1,
+                span: 0..2,
             }
         );
     }
 
+    #[test]
+    fn continues_after_error() {
+        let mut tokenizer = Tokenizer::new("GBP $$$ GBP\r\n%%%\nGBP".to_string());
+
+        assert_eq!(
+            tokenizer.next().map(|t| t.map(|t| t.kind)),
+            Some(Ok(TokenKind::Currency("GBP".to_string())))
+        );
+        assert!(
+            tokenizer
+                .next()
+                .expect("not at the end of the buffer")
+                .is_err()
+        );
+        assert_eq!(
+            tokenizer.next().map(|t| t.map(|t| t.kind)),
+            Some(Ok(TokenKind::Newline))
+        );
+        assert!(
+            tokenizer
+                .next()
+                .expect("not at the end of the buffer")
+                .is_err()
+        );
+        assert_eq!(
+            tokenizer.next().map(|t| t.map(|t| t.kind)),
+            Some(Ok(TokenKind::Newline))
+        );
+        assert_eq!(
+            tokenizer.next().map(|t| t.map(|t| t.kind)),
+            Some(Ok(TokenKind::Currency("GBP".to_string())))
+        );
+        assert!(tokenizer.next().is_none());
+    }
+
     #[test]
     fn account_name_capitalized() {
         let mut tokenizer = Tokenizer::new(r#"Assets:nOtCapitalized"#.to_string());
@@ -764,6 +1156,122 @@ mod tests {
                 msg: "unexpected character sequence".to_string(),
                 line: 1,
                 column: 1,
+                span: 0..21,
+            }
+        );
+    }
+
+    #[test]
+    fn tx_description_contents() {
+        let mut tokenizer = Tokenizer::new(r#""Café \"Nerö\"" "back\\slash \n" """#.to_string());
+
+        assert_eq!(
+            tokenizer.next_token(),
+            Ok(Some(Token {
+                kind: TokenKind::TxDescription(r#"Café "Nerö""#.to_string()),
+                line: 1,
+                column: 1,
+                span: 0..17,
+            }))
+        );
+
+        assert_eq!(
+            tokenizer.next_token().map(|t| t.map(|t| t.kind)),
+            Ok(Some(TokenKind::TxDescription(
+                r#"back\slash \n"#.to_string()
+            )))
+        );
+
+        assert_eq!(
+            tokenizer.next_token().map(|t| t.map(|t| t.kind)),
+            Ok(Some(TokenKind::TxDescription("".to_string())))
+        );
+
+        assert_eq!(tokenizer.next_token(), Ok(None));
+
+        // an unterminated string is an error
+        let mut tokenizer = Tokenizer::new(r#""not closed\""#.to_string());
+        assert_eq!(
+            tokenizer.next_token().unwrap_err(),
+            TokenizeError {
+                msg: "unexpected character sequence".to_string(),
+                line: 1,
+                column: 1,
+                span: 0..4,
+            }
+        );
+    }
+
+    #[test]
+    fn nested_account_names() {
+        let mut tokenizer =
+            Tokenizer::new("\nAssets:Bank:Checking:Joint Liabilities:2024:Card-1 Assets:Cash");
+        tokenizer.next_token().expect("the newline should parse ok");
+
+        assert_eq!(
+            tokenizer.next_token(),
+            Ok(Some(Token {
+                kind: TokenKind::Account(AccountId {
+                    type_: AccountType::Asset,
+                    components: vec![
+                        "Bank".to_string(),
+                        "Checking".to_string(),
+                        "Joint".to_string()
+                    ]
+                }),
+                line: 2,
+                column: 1,
+                span: 1..27,
+            }))
+        );
+
+        assert_eq!(
+            tokenizer.next_token(),
+            Ok(Some(Token {
+                kind: TokenKind::Account(AccountId {
+                    type_: AccountType::Liability,
+                    components: vec!["2024".to_string(), "Card-1".to_string()]
+                }),
+                line: 2,
+                column: 28,
+                span: 28..51,
+            }))
+        );
+
+        assert_eq!(
+            tokenizer.next_token(),
+            Ok(Some(Token {
+                kind: TokenKind::Account(AccountId {
+                    type_: AccountType::Asset,
+                    components: vec!["Cash".to_string()]
+                }),
+                line: 2,
+                column: 52,
+                span: 52..63,
+            }))
+        );
+
+        // every component must start with a capital letter or digit
+        let mut tokenizer = Tokenizer::new("Assets:Bank:checking");
+        assert_eq!(
+            tokenizer.next_token().unwrap_err(),
+            TokenizeError {
+                msg: "unexpected character sequence".to_string(),
+                line: 1,
+                column: 1,
+                span: 0..20,
+            }
+        );
+
+        // a root on its own isn't an account
+        let mut tokenizer = Tokenizer::new("Assets: GBP");
+        assert_eq!(
+            tokenizer.next_token().unwrap_err(),
+            TokenizeError {
+                msg: "unexpected character sequence".to_string(),
+                line: 1,
+                column: 1,
+                span: 0..7,
             }
         );
     }
@@ -786,6 +1294,7 @@ mod tests {
                 msg: "unexpected character sequence".to_string(),
                 line: 2,
                 column: 2,
+                span: 12..23,
             }
         )
     }
@@ -812,4 +1321,682 @@ mod tests {
         tokenizer.set_cursor(7);
         assert_eq!(tokenizer.current_line_column(), (2, 2));
     }
+
+    /// Sources which exercise the edges of each kind of token.
+    pub(crate) const SOURCES: [&str; 25] = [
+        "",
+        "option \"operating_currency\" \"GBP\"\n",
+        "option\t\"title\"\n\"My Accounts\" ; a comment\r\n",
+        "option \"name_assets\" \"Actifs\"\n2023-02-01 open Actifs:Banque EUR\n",
+        "option \"\" \"x\"\noption \"a\" \"b\"c\n",
+        "2023-02-01 open Assets:Bank:2024-Checking GBP,EUR,USD \"FIFO\"\n",
+        "2023-02-01 open Assets:Bank GBP,\n2023-02-01 open Assets:bank\n",
+        "2023-02-01 balance Assets:Bank -1,234.56 GBP\n",
+        "2023-02-01 close Assets:Bank\n2023-02-01 pad Assets:Bank Equity:Opening\n",
+        "2023-02-01 price BTC 30,000.00GBP\n2023-02-01 include \"*.beancount\"\n",
+        "2023-02-03 * \"Payee\" \"Narration with \\\"quotes\\\" and \\\\\"\n",
+        "  Assets:Stocks  10 ABC {{1,000 GBP, 2023-01-01}} @@ 1,100 GBP\n",
+        "  Assets:Stocks  -5 ABC {100 GBP} @ 110 GBP\n  Income:Gains\n",
+        "  Assets:Stocks  5 ABC {2023-01-01}\n  Assets:Stocks  5 ABC {}\n",
+        "2023-02-0 open\n20230-02-01\n2023-02-01x\n2023-02-01}\n",
+        "1,23 GBP 1,234,5678 GBP 12345,678 GBP 1. GBP .5 GBP 1.5.5 GBP -GBP --1 GBP\n",
+        "1 GB 1 GBp 1 GBP, 1 GBP} 1\tGBP 1 \t GBPX\n",
+        "12345678901234567890123456789 GBP\n",
+        "@@@ @ @@x {{{ }}} ,, ** *x\n",
+        "\"unterminated\n\"a\"b \"\" \"\\\n\" \"é\\é\"\n",
+        "\"\\\n\" \"\" \"é\\é\"\n",
+        "Assets Assets: Assets:: Assets:a Assetz:Bank ASSETS:Bank Expenses:Café\n",
+        "Ünïcode é 😀 \r \r\r\n\n\t\t\n",
+        "; only a comment",
+        "2023-02-01 open Assets:Bank GBP ; trailing\n; whole line\n",
+    ];
+
+    /// Fragments of sources, which are put together in random orders by [`generated_sources`].
+    const FRAGMENTS: [&str; 52] = [
+        "2023-02-01",
+        "1999-12-31",
+        "option \"name_assets\" \"Actifs\"",
+        "option",
+        "open",
+        "balance",
+        "close",
+        "pad",
+        "price",
+        "include",
+        "*",
+        " ",
+        " ",
+        "\t",
+        "\n",
+        "\n",
+        "\r\n",
+        "\r",
+        "\"",
+        "\\",
+        "\\\"",
+        "\"title\"",
+        "Assets",
+        "Liabilities",
+        "Actifs",
+        ":",
+        ":",
+        "Bank",
+        "2024",
+        "bank",
+        "-",
+        "GBP",
+        "US",
+        "12",
+        "1,234",
+        "0.5",
+        ".",
+        ",",
+        "@",
+        "@@",
+        "{",
+        "}",
+        "{{",
+        "}}",
+        ";",
+        "é",
+        "Ünïcode",
+        "x",
+        "ABC",
+        "Z",
+        "Expenses:Food",
+        "-3.20 GBP",
+    ];
+
+    /// Returns sources made of random sequences of [`FRAGMENTS`]. The generator is seeded so that
+    /// any failure can be reproduced.
+    pub(crate) fn generated_sources(count: usize) -> impl Iterator<Item = String> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = move |n: usize| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+
+        (0..count).map(move |_| {
+            let len = random(16) + 1;
+            (0..len)
+                .map(|_| FRAGMENTS[random(FRAGMENTS.len())])
+                .collect()
+        })
+    }
+
+    /// Asserts that the [`Tokenizer`] returns the same tokens and errors for `source` as the regex
+    /// tokenizer it replaced.
+    fn assert_same_tokens(source: &str) {
+        let tokens: Vec<_> = Tokenizer::new(source).collect();
+        let expected: Vec<_> = regex_reference::RegexTokenizer::new(source).collect();
+        assert_eq!(tokens, expected, "{source:?}");
+    }
+
+    #[test]
+    fn same_tokens_as_regex_tokenizer() {
+        for source in SOURCES {
+            assert_same_tokens(source);
+        }
+        for source in generated_sources(20_000) {
+            assert_same_tokens(&source);
+        }
+    }
+
+    #[test]
+    fn keep_trivia() {
+        let source = "2023-02-01 open\t$$$ x ; note\n  ; comment\n";
+        let mut tokenizer = Tokenizer::new(source).keep_trivia();
+
+        let mut found = vec![];
+        while let Some(token) = tokenizer.next() {
+            found.push((token.is_ok(), tokenizer.take_trivia()));
+        }
+        found.push((true, tokenizer.take_trivia()));
+
+        let trivia = |kind, span| Trivia { kind, span };
+        assert_eq!(
+            found,
+            vec![
+                (true, vec![]),
+                (true, vec![trivia(TriviaKind::Whitespace, 10..11)]),
+                (
+                    false,
+                    vec![
+                        trivia(TriviaKind::Whitespace, 15..16),
+                        trivia(TriviaKind::Skipped, 19..28),
+                    ]
+                ),
+                (true, vec![]),
+                (
+                    true,
+                    vec![
+                        trivia(TriviaKind::Whitespace, 29..31),
+                        trivia(TriviaKind::Comment, 31..40),
+                    ]
+                ),
+                (true, vec![]),
+            ]
+        );
+
+        // The tokens are the same whether the trivia is kept or not
+        for source in SOURCES {
+            assert_eq!(
+                Tokenizer::new(source).keep_trivia().collect::<Vec<_>>(),
+                Tokenizer::new(source).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn from_reader_works() {
+        // Reading a few bytes at a time gives the same tokens as tokenizing the whole source
+        for source in SOURCES
+            .into_iter()
+            .map(str::to_string)
+            .chain(generated_sources(5_000))
+        {
+            let reader = io::BufReader::with_capacity(4, io::Cursor::new(source.clone()));
+            assert_eq!(
+                Tokenizer::from_reader(reader).collect::<Vec<_>>(),
+                Tokenizer::new(source.as_str()).collect::<Vec<_>>(),
+                "{source:?}"
+            );
+        }
+
+        // Only the current line is kept
+        let transaction = "2023-02-03 * \"Coffee\"\n  Expenses:Coffee  3.20 GBP\n  Assets:Bank\n\n";
+        let mut tokenizer = Tokenizer::from_reader(io::Cursor::new(transaction.repeat(1_000)));
+        let mut count = 0;
+        while let Some(token) = tokenizer.next() {
+            assert!(token.is_ok());
+            assert!(tokenizer.buffer.len() <= 30, "{}", tokenizer.buffer.len());
+            count += 1;
+        }
+        assert_eq!(count, 10_000);
+
+        // A read error ends the tokens
+        let source = b"2023-02-01 open\n\xff\n".to_vec();
+        let tokens: Vec<_> = Tokenizer::from_reader(io::Cursor::new(source)).collect();
+        assert_eq!(
+            tokens.last(),
+            Some(&Err(TokenizeError {
+                msg: "can't read the source: stream did not contain valid UTF-8".to_string(),
+                line: 2,
+                column: 1,
+                span: 16..16,
+            }))
+        );
+        assert_eq!(tokens.len(), 4);
+    }
+
+    /// The regex tokenizer which [`lex`] replaced, kept as a reference for the differential
+    /// tests.
+    mod regex_reference {
+        use std::sync::LazyLock;
+
+        use regex::Regex;
+
+        use super::super::*;
+
+        static DATE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(\d{4}-\d{2}-\d{2})(?:[ \t\n\r}]|$)"#)
+                .expect("hard coded regex is valid")
+        });
+
+        static AMOUNT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(
+                r#"^(-?(?:\d{1,3}(?:,\d{3})*|\d+)(?:\.\d+)?)[ \t]*([A-Z]{3,})(?:[ \t\n\r,}]|$)"#,
+            )
+            .expect("hard coded regex is valid")
+        });
+
+        static WHITESPACE_REGEX: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r#"^[ \t]+"#).expect("hard coded regex is valid"));
+
+        // An option line, the second and third capture groups are the name and the value of the option.
+        static OPTION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(option\s+"([^"]+)"\s+"([^"]+)")(?:[ \t\n\r]|$)"#)
+                .expect("hard coded regex is valid")
+        });
+
+        static DIRECTIVE_OPEN_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(open)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        static DIRECTIVE_BALANCE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(balance)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        static DIRECTIVE_CLOSE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(close)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        static DIRECTIVE_PAD_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(pad)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        static DIRECTIVE_PRICE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(price)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        static DIRECTIVE_INCLUDE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(include)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        static DIRECTIVE_POST_TX_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(\*)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        // An account is a root followed by one or more components, each preceded by a colon. Components
+        // may start with a capital letter or a digit e.g. `Assets:Bank:2024:Checking`.
+        // The first capture group is the root, which is only an account if it's one of the root names.
+        static ACCOUNT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(\p{Lu}[\p{L}\p{N}-]*)((?::[A-Z0-9][A-Za-z0-9-]*)+)(?:[ \t\n\r]|$)"#)
+                .expect("hard coded regex is valid")
+        });
+
+        static CURRENCY_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^([A-Z]+)(?:[ \t\n\r,}]|$)"#).expect("hard coded regex is valid")
+        });
+
+        static COMMENT_REGEX: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r#"^;[^\r\n]*"#).expect("hard coded regex is valid"));
+
+        // A double quoted string. Quotes and backslashes inside the string are escaped with a backslash.
+        static TX_DESCRIPTION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^"((?:[^"\\]|\\.)*)"(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        // `@` precedes the price of each unit and `@@` precedes the total price.
+        static AT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(@@|@)(?:[ \t\n\r]|$)"#).expect("hard coded regex is valid")
+        });
+
+        // Braces surround a cost, `{{` and `}}` surround a total cost. Like commas they don't need to be
+        // followed by whitespace.
+        static BRACE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"^(?:\{\{|\}\}|\{|\})"#).expect("hard coded regex is valid")
+        });
+
+        // A comma separates a list of currencies, it doesn't need to be followed by whitespace
+        static COMMA_REGEX: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r#"^,"#).expect("hard coded regex is valid"));
+
+        static NEWLINE_REGEX: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r#"^\r?\n"#).expect("hard coded regex is valid"));
+
+        pub(super) struct RegexTokenizer {
+            buffer: String,
+            cursor: usize,
+            root_names: RootNames,
+        }
+
+        impl RegexTokenizer {
+            pub(super) fn new(buffer: &str) -> Self {
+                RegexTokenizer {
+                    buffer: buffer.to_string(),
+                    cursor: 0,
+                    root_names: RootNames::default(),
+                }
+            }
+
+            fn current_line_column(&self) -> (usize, usize) {
+                let before = &self.buffer[..self.cursor];
+                let line = before.matches('\n').count() + 1;
+                let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                (line, before[line_start..].chars().count() + 1)
+            }
+
+            fn skip_to_end_of_line(&mut self) {
+                self.cursor = match self.buffer[self.cursor..].find('\n') {
+                    Some(index) => {
+                        let newline = self.cursor + index;
+                        if newline > self.cursor && self.buffer[..newline].ends_with('\r') {
+                            newline - 1
+                        } else {
+                            newline
+                        }
+                    }
+                    None => self.buffer.len(),
+                };
+            }
+        }
+
+        impl Iterator for RegexTokenizer {
+            type Item = Result<Token, TokenizeError>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.next_token().transpose()
+            }
+        }
+
+        impl RegexTokenizer {
+            fn next_token(&mut self) -> Result<Option<Token>, TokenizeError> {
+                let start = self.cursor;
+                if self.cursor >= self.buffer.len() {
+                    Ok(None)
+                } else if let Some(whitespace) = WHITESPACE_REGEX.find(&self.buffer[self.cursor..]) {
+                    self.cursor += whitespace.end();
+                    self.next_token()
+                } else if let Some(captures) = OPTION_REGEX.captures(&self.buffer[self.cursor..]) {
+                    let group = |i| {
+                        captures
+                            .get(i)
+                            .expect("if the entire regex matches then the capture groups will not be None")
+                    };
+                    let (line, column) = self.current_line_column();
+                    let (name, value) = (group(2).as_str(), group(3).as_str());
+                    // Invalid names are left unchanged, the parser reports them
+                    let _ = self.root_names.set(name, value);
+                    let kind = TokenKind::OptionLine {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    };
+                    self.cursor += group(1).end();
+                    Ok(Some(Token {
+                        kind,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(comment) = COMMENT_REGEX.find(&self.buffer[self.cursor..]) {
+                    // we ignore comments
+                    self.cursor += comment.end();
+                    self.next_token()
+                } else if let Some(date) = DATE_REGEX.captures(&self.buffer[self.cursor..]).map(|c| {
+                    c.get(1)
+                        .expect("if the entire regex matches then the first capture group will not be None")
+                }) {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += date.end();
+                    let date = date
+                        .as_str()
+                        .parse()
+                        .expect("the regex guarantees that parsing won't fail");
+                    Ok(Some(Token {
+                        kind: TokenKind::Date(date),
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(captures) = AMOUNT_REGEX.captures(&self.buffer[self.cursor..]) {
+                    let amount = captures
+                        .get(1)
+                        .expect("if there was a match there will be a 1 capture group");
+                    let currency = captures
+                        .get(2)
+                        .expect("if there was a match there will be a 1 capture group");
+                    let Ok(amount) = amount.as_str().replace(",", "").parse() else {
+                        // the regex accepts commas e.g. 9,000 which won't parse so we strip them out
+                        let (line, column) = self.current_line_column();
+                        let span = start..start + currency.end();
+                        self.skip_to_end_of_line();
+                        return Err(TokenizeError {
+                            msg: "decimal has too many digits".to_string(),
+                            line,
+                            column,
+                            span,
+                        });
+                    };
+                    let (line, column) = self.current_line_column();
+                    self.cursor += currency.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::Amount(Amount {
+                            currency: currency.as_str().to_string(),
+                            amount,
+                        }),
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(directive_open) = DIRECTIVE_OPEN_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += directive_open.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::DirectiveOpen,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(directive_balance) = DIRECTIVE_BALANCE_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += directive_balance.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::DirectiveBalance,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(directive_close) = DIRECTIVE_CLOSE_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += directive_close.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::DirectiveClose,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(directive_pad) = DIRECTIVE_PAD_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += directive_pad.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::DirectivePad,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(directive_price) = DIRECTIVE_PRICE_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += directive_price.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::DirectivePrice,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(directive_include) = DIRECTIVE_INCLUDE_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += directive_include.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::DirectiveInclude,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(directive_post_tx) = DIRECTIVE_POST_TX_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += directive_post_tx.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::DirectivePostTx,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some((acct_type, full_account)) = ACCOUNT_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .and_then(|c| {
+                        let root = c
+                            .get(1)
+                            .expect("if there was a match there will be a 1 capture group");
+                        Some((self.root_names.account_type(root.as_str())?, c))
+                    })
+                {
+                    let acct_components = full_account
+                        .get(2)
+                        .expect("if there was a match there will be a 2 capture group");
+
+                    let (line, column) = self.current_line_column();
+                    self.cursor += acct_components.end();
+
+                    // The capture group starts with a colon so we skip the first (empty) element.
+                    let components = acct_components
+                        .as_str()
+                        .split(':')
+                        .skip(1)
+                        .map(str::to_string)
+                        .collect();
+
+                    Ok(Some(Token {
+                        kind: TokenKind::Account(AccountId {
+                            type_: acct_type,
+                            components,
+                        }),
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(currency) =
+                    CURRENCY_REGEX
+                        .captures(&self.buffer[self.cursor..])
+                        .map(|c| {
+                            c.get(1).expect(
+                                "if the entire regex matches then the first capture group will not be None",
+                            )
+                        })
+                {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += currency.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::Currency(currency.as_str().to_string()),
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(tx_description) = TX_DESCRIPTION_REGEX
+                    .captures(&self.buffer[self.cursor..])
+                    .map(|c| {
+                        c.get(1).expect(
+                            "if the entire regex matches then the first capture group will not be None",
+                        )
+                    })
+                {
+                    let (line, column) = self.current_line_column();
+                    // the capture group excludes the closing quote
+                    self.cursor += tx_description.end() + 1;
+                    Ok(Some(Token {
+                        kind: TokenKind::TxDescription(unescape(tx_description.as_str())),
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(at) = AT_REGEX.captures(&self.buffer[self.cursor..]).map(|c| {
+                    c.get(1)
+                        .expect("if the entire regex matches then the first capture group will not be None")
+                }) {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += at.end();
+                    let kind = if at.as_str() == "@@" {
+                        TokenKind::DoubleAt
+                    } else {
+                        TokenKind::At
+                    };
+                    Ok(Some(Token {
+                        kind,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(brace) = BRACE_REGEX.find(&self.buffer[self.cursor..]) {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += brace.end();
+                    let kind = match brace.as_str() {
+                        "{{" => TokenKind::DoubleLeftBrace,
+                        "}}" => TokenKind::DoubleRightBrace,
+                        "{" => TokenKind::LeftBrace,
+                        _ => TokenKind::RightBrace,
+                    };
+                    Ok(Some(Token {
+                        kind,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(comma) = COMMA_REGEX.find(&self.buffer[self.cursor..]) {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += comma.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::Comma,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else if let Some(newline) = NEWLINE_REGEX.find(&self.buffer[self.cursor..]) {
+                    let (line, column) = self.current_line_column();
+                    self.cursor += newline.end();
+                    Ok(Some(Token {
+                        kind: TokenKind::Newline,
+                        line,
+                        column,
+                        span: start..self.cursor,
+                    }))
+                } else {
+                    let (line, column) = self.current_line_column();
+                    // The error spans the unrecognized sequence i.e. up to the next whitespace character.
+                    let span = start
+                        ..self.buffer[start..]
+                            .find(char::is_whitespace)
+                            .map_or(self.buffer.len(), |end| start + end);
+                    self.skip_to_end_of_line();
+                    Err(TokenizeError {
+                        msg: "unexpected character sequence".to_string(),
+                        line,
+                        column,
+                        span,
+                    })
+                }
+            }
+        }
+    }
 }
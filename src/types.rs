@@ -1,7 +1,7 @@
 use ::rust_decimal::Decimal;
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum AccountType {
     Equity,
     Liability,
@@ -10,13 +10,24 @@ pub enum AccountType {
     Expense,
 }
 
-/// Account identifier.
-#[derive(Debug, PartialEq, Eq)]
+/// Account identifier. An account name is a root, given by the [`AccountType`], followed by one
+/// or more colon separated components e.g. `Assets:Bank:Checking:Joint` has type
+/// [`AccountType::Asset`] and components `["Bank", "Checking", "Joint"]`. Two identifiers are
+/// equal only if they have the same type and exactly the same components.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct AccountId {
-    pub name: String,
+    pub components: Vec<String>,
     pub type_: AccountType,
 }
 
+impl AccountId {
+    /// Returns the components of the account name joined by a colon, excluding the root e.g.
+    /// `Bank:Checking:Joint` for `Assets:Bank:Checking:Joint`.
+    pub fn name(&self) -> String {
+        self.components.join(":")
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct AccountFromStrError(String);
 
@@ -28,6 +39,8 @@ impl std::fmt::Display for AccountFromStrError {
 
 impl std::error::Error for AccountFromStrError {}
 
+/// Parses the default name of a root account e.g. `Assets`. The names can be changed with options,
+/// see [`RootNames`](crate::options::RootNames).
 impl FromStr for AccountType {
     type Err = AccountFromStrError;
 
@@ -43,8 +56,79 @@ impl FromStr for AccountType {
     }
 }
 
+/// How reductions of lots held at cost are matched against the lots in an account's inventory.
+/// The method is given on the open directive e.g. `2023-02-01 open Assets:Broker VWRL "FIFO"`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BookingMethod {
+    /// The cost specification must match exactly one lot, unless the reduction is the same size
+    /// as all the matching lots put together.
+    #[default]
+    Strict,
+    /// Matching lots are reduced oldest first.
+    Fifo,
+    /// Matching lots are reduced newest first.
+    Lifo,
+    /// Matching lots are merged into a single lot at their average cost before being reduced.
+    Average,
+    /// Reductions aren't matched against lots, they're added to the inventory as they are.
+    None,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct BookingMethodFromStrError(String);
+
+impl std::fmt::Display for BookingMethodFromStrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unrecognized booking method {}", self.0)
+    }
+}
+
+impl std::error::Error for BookingMethodFromStrError {}
+
+impl FromStr for BookingMethod {
+    type Err = BookingMethodFromStrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "STRICT" => Ok(BookingMethod::Strict),
+            "FIFO" => Ok(BookingMethod::Fifo),
+            "LIFO" => Ok(BookingMethod::Lifo),
+            "AVERAGE" => Ok(BookingMethod::Average),
+            "NONE" => Ok(BookingMethod::None),
+            x => Err(BookingMethodFromStrError(x.to_string())),
+        }
+    }
+}
+
+/// A number of units of a currency or commodity e.g. `3.50 GBP`.
 #[derive(Debug, PartialEq)]
 pub struct Amount {
     pub(crate) currency: String,
     pub(crate) amount: Decimal,
 }
+
+impl Amount {
+    /// Amount constructor.
+    pub fn new(amount: Decimal, currency: impl Into<String>) -> Self {
+        Amount {
+            currency: currency.into(),
+            amount,
+        }
+    }
+
+    /// Returns the number of units.
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    /// Returns the currency or commodity.
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+}
+
+impl std::fmt::Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}